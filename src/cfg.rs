@@ -0,0 +1,355 @@
+use crate::def::*;
+
+use std::collections::HashMap;
+
+pub type BlockId = usize;
+
+/* Straight-line instructions; control only leaves through the edges */
+#[derive(Default)]
+pub struct Block {
+    pub inst: Vec<Inst>,
+    pub succ: Vec<BlockId>,
+}
+
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub entry: BlockId,
+    pub exit: BlockId,
+}
+
+/*
+ * Whether a loop condition always or never holds. A missing condition, as in
+ * `for (;;)`, always does.
+ */
+fn constant(cond: &Inst) -> Option<bool> {
+    match cond {
+        Inst::InstSet(_, inst_set) if inst_set.is_empty() => Some(true),
+        Inst::Eval(_, ExprDescriptor::Literal(_, value)) => {
+            let digits = value
+                .trim_start_matches("0x")
+                .trim_start_matches("0X")
+                .trim_end_matches(['u', 'U', 'l', 'L']);
+            Some(!digits.chars().all(|c| c == '0'))
+        }
+        _ => None,
+    }
+}
+
+/* Variables declared directly in a scope */
+fn declared(inst_list: &[Inst]) -> Vec<String> {
+    inst_list
+        .iter()
+        .filter_map(|inst| match inst {
+            Inst::VarDecl(_, label, _) => Some(label.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn end_of(span: &Span) -> Span {
+    Span {
+        begin: span.end.clone(),
+        end: span.end.clone(),
+    }
+}
+
+/* A jump target and how many scopes stay open when jumping there */
+type Target = (BlockId, usize);
+
+/* The enclosing `switch`, where its `case` labels branch from */
+struct Switch {
+    dispatch: BlockId,
+    has_default: bool,
+}
+
+struct Builder {
+    blocks: Vec<Block>,
+    current: BlockId,
+    exit: BlockId,
+    breaks: Vec<Target>,    /* innermost last */
+    continues: Vec<Target>, /* innermost last */
+    switches: Vec<Switch>,
+    scopes: Vec<Vec<String>>,         /* innermost last */
+    labels: HashMap<String, BlockId>, /* a goto may come before its label */
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block::default());
+        self.blocks.len() - 1
+    }
+
+    fn edge(&mut self, from: BlockId, to: BlockId) {
+        if !self.blocks[from].succ.contains(&to) {
+            self.blocks[from].succ.push(to);
+        }
+    }
+
+    /* Branch on a loop condition, leaving out the edge it can never take */
+    fn branch(&mut self, cond: BlockId, value: Option<bool>, taken: BlockId, not_taken: BlockId) {
+        if value != Some(false) {
+            self.edge(cond, taken);
+        }
+        if value != Some(true) {
+            self.edge(cond, not_taken);
+        }
+    }
+
+    /* Whatever follows a jump has no predecessor until something branches to it */
+    fn start_unreachable(&mut self) {
+        self.current = self.new_block();
+    }
+
+    fn end_scope(&mut self, span: Span, labels: Vec<String>) {
+        if !labels.is_empty() {
+            self.blocks[self.current]
+                .inst
+                .push(Inst::EndScope(span, labels));
+        }
+    }
+
+    /* Scopes left by the jump end before it */
+    fn jump(&mut self, span: &Span, target: Option<Target>) {
+        if let Some((block, depth)) = target {
+            let left: Vec<Vec<String>> = self.scopes[depth..].iter().rev().cloned().collect();
+            for labels in left {
+                self.end_scope(span.clone(), labels);
+            }
+            self.edge(self.current, block);
+        }
+        self.start_unreachable();
+    }
+
+    fn lower_scope(&mut self, span: &Span, inst_list: &[Inst]) {
+        self.scopes.push(declared(inst_list));
+        for inst in inst_list {
+            self.lower(inst);
+        }
+        let labels = self.scopes.pop().unwrap();
+        self.end_scope(end_of(span), labels);
+    }
+
+    fn label(&mut self, id: &str) -> BlockId {
+        if let Some(&block) = self.labels.get(id) {
+            return block;
+        }
+        let block = self.new_block();
+        self.labels.insert(id.to_string(), block);
+        block
+    }
+
+    fn lower_loop_body(&mut self, body: &Inst, after: BlockId, next: BlockId) {
+        self.breaks.push((after, self.scopes.len()));
+        self.continues.push((next, self.scopes.len()));
+        self.lower(body);
+        self.continues.pop();
+        self.breaks.pop();
+    }
+
+    /* A `case` or `default` is entered from the switch and by falling through */
+    fn lower_label(&mut self, inst_list: &[Inst]) {
+        let block = self.new_block();
+        self.edge(self.current, block);
+        if let Some(switch) = self.switches.last() {
+            let dispatch = switch.dispatch;
+            self.edge(dispatch, block);
+        }
+
+        self.current = block;
+        for inst in inst_list {
+            self.lower(inst);
+        }
+    }
+
+    fn lower(&mut self, inst: &Inst) {
+        match inst {
+            Inst::InstSet(span, inst_set) => {
+                self.lower_scope(span, inst_set);
+            }
+            Inst::If(_, inst_list) => {
+                self.lower(&inst_list[0]);
+                let cond = self.current;
+
+                let then_block = self.new_block();
+                let join = self.new_block();
+
+                self.edge(cond, then_block);
+                self.current = then_block;
+                self.lower(&inst_list[1]);
+                self.edge(self.current, join);
+
+                match inst_list.get(2) {
+                    Some(path) => {
+                        let else_block = self.new_block();
+                        self.edge(cond, else_block);
+                        self.current = else_block;
+                        self.lower(path);
+                        self.edge(self.current, join);
+                    }
+                    None => self.edge(cond, join),
+                }
+
+                self.current = join;
+            }
+            Inst::While(_, inst_list) => {
+                let header = self.new_block();
+                self.edge(self.current, header);
+                self.current = header;
+                self.lower(&inst_list[0]);
+                let cond = self.current;
+
+                let body = self.new_block();
+                let after = self.new_block();
+                self.branch(cond, constant(&inst_list[0]), body, after);
+
+                self.current = body;
+                self.lower_loop_body(&inst_list[1], after, header);
+                self.edge(self.current, header);
+
+                self.current = after;
+            }
+            Inst::DoWhile(_, inst_list) => {
+                let body = self.new_block();
+                let cond = self.new_block();
+                let after = self.new_block();
+
+                self.edge(self.current, body);
+                self.current = body;
+                self.lower_loop_body(&inst_list[0], after, cond);
+
+                self.edge(self.current, cond);
+                self.current = cond;
+                self.lower(&inst_list[1]);
+                let cond_end = self.current;
+                self.branch(cond_end, constant(&inst_list[1]), body, after);
+
+                self.current = after;
+            }
+            Inst::For(span, inst_list) => {
+                /* Variables declared in the init live until the loop is done */
+                let init = match &inst_list[0] {
+                    Inst::InstSet(_, inst_set) => inst_set.as_slice(),
+                    inst => std::slice::from_ref(inst),
+                };
+                self.scopes.push(declared(init));
+                for inst in init {
+                    self.lower(inst);
+                }
+
+                let header = self.new_block();
+                self.edge(self.current, header);
+                self.current = header;
+                self.lower(&inst_list[1]);
+                let cond = self.current;
+
+                let body = self.new_block();
+                let inc = self.new_block();
+                let after = self.new_block();
+                self.branch(cond, constant(&inst_list[1]), body, after);
+
+                self.current = body;
+                self.lower_loop_body(&inst_list[3], after, inc);
+                self.edge(self.current, inc);
+
+                self.current = inc;
+                self.lower(&inst_list[2]);
+                self.edge(self.current, header);
+
+                self.current = after;
+                let labels = self.scopes.pop().unwrap();
+                self.end_scope(end_of(span), labels);
+            }
+            Inst::Switch(_, inst_list) => {
+                self.lower(&inst_list[0]);
+                let dispatch = self.current;
+                let after = self.new_block();
+
+                /* Statements before the first label are skipped */
+                self.start_unreachable();
+                self.switches.push(Switch {
+                    dispatch,
+                    has_default: false,
+                });
+                self.breaks.push((after, self.scopes.len()));
+                self.lower(&inst_list[1]);
+                self.breaks.pop();
+                let switch = self.switches.pop().unwrap();
+
+                self.edge(self.current, after);
+                if !switch.has_default {
+                    self.edge(dispatch, after);
+                }
+
+                self.current = after;
+            }
+            Inst::Case(_, inst_list) => {
+                self.lower_label(inst_list);
+            }
+            Inst::Default(_, inst_list) => {
+                if let Some(switch) = self.switches.last_mut() {
+                    switch.has_default = true;
+                }
+                self.lower_label(inst_list);
+            }
+            Inst::Break(span) => {
+                let target = self.breaks.last().copied();
+                self.jump(span, target);
+            }
+            Inst::Continue(span) => {
+                let target = self.continues.last().copied();
+                self.jump(span, target);
+            }
+            Inst::Label(_, id, inst_list) => {
+                let block = self.label(id);
+                self.edge(self.current, block);
+                self.current = block;
+                for inst in inst_list {
+                    self.lower(inst);
+                }
+            }
+            /* Where the label sits is not known yet; what a goto leaves is checked on return */
+            Inst::Goto(span, id) => {
+                let target = (self.label(id), self.scopes.len());
+                self.jump(span, Some(target));
+            }
+            Inst::ReturnStmt(_, _) => {
+                self.blocks[self.current].inst.push(inst.clone());
+                self.edge(self.current, self.exit);
+                self.start_unreachable();
+            }
+            _ => self.blocks[self.current].inst.push(inst.clone()),
+        }
+    }
+}
+
+impl Cfg {
+    pub fn build(function: &Function) -> Cfg {
+        let mut builder = Builder {
+            blocks: Vec::new(),
+            current: 0,
+            exit: 0,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+            switches: Vec::new(),
+            labels: HashMap::new(),
+            scopes: Vec::new(),
+        };
+        let entry = builder.new_block();
+        builder.exit = builder.new_block();
+        builder.current = entry;
+
+        for inst in function.inst.iter() {
+            builder.lower(inst);
+        }
+
+        /* Falling off the end of the body returns too */
+        let end = function.body_span.as_ref().unwrap_or(&function.span);
+        builder.lower(&Inst::ReturnStmt(end_of(end), None));
+
+        Cfg {
+            blocks: builder.blocks,
+            entry,
+            exit: builder.exit,
+        }
+    }
+}
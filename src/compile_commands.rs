@@ -0,0 +1,186 @@
+use serde_json::Value;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct CompileCommand {
+    pub file: PathBuf,
+    pub args: Vec<String>,
+}
+
+/* Split a `command` string the way a POSIX shell would for plain words */
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    words
+}
+
+/*
+ * Keep the flags that affect parsing. The compiler itself, `-c`, the output
+ * and the source file are dropped since libclang supplies its own. The source
+ * may be spelled relative to `directory` in one place and absolute in another.
+ */
+fn parse_flags(argv: &[String], directory: &str, file: &str) -> Vec<String> {
+    let directory = Path::new(directory);
+    let file = directory.join(file);
+    let mut args = Vec::new();
+    let mut argv = argv.iter().skip(1);
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-c" => {}
+            "-o" => {
+                let _ = argv.next();
+            }
+            _ if arg.starts_with("-o") => {}
+            _ if directory.join(arg) == file => {}
+            _ => args.push(arg.clone()),
+        }
+    }
+
+    args
+}
+
+fn parse_entry(entry: &Value) -> Result<CompileCommand, String> {
+    let directory = entry
+        .get("directory")
+        .and_then(|v| v.as_str())
+        .ok_or("entry without 'directory'")?;
+    let file = entry
+        .get("file")
+        .and_then(|v| v.as_str())
+        .ok_or("entry without 'file'")?;
+
+    let argv: Vec<String> = if let Some(arguments) = entry.get("arguments") {
+        arguments
+            .as_array()
+            .ok_or("'arguments' is not a list")?
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_string())
+            .collect()
+    } else if let Some(command) = entry.get("command").and_then(|v| v.as_str()) {
+        split_command(command)
+    } else {
+        return Err(format!("{}: entry without 'arguments' or 'command'", file));
+    };
+
+    /* Relative paths in the flags are relative to the entry's directory */
+    let mut args = vec!["-working-directory".to_string(), directory.to_string()];
+    args.extend(parse_flags(&argv, directory, file));
+
+    Ok(CompileCommand {
+        file: Path::new(directory).join(file),
+        args,
+    })
+}
+
+/* Accepts either the database itself or the build directory holding it */
+pub fn load(path: &Path) -> Result<Vec<CompileCommand>, String> {
+    let path = if path.is_dir() {
+        path.join("compile_commands.json")
+    } else {
+        path.to_path_buf()
+    };
+
+    let json_content =
+        fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let parsed_json: Value =
+        serde_json::from_str(&json_content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    parsed_json
+        .as_array()
+        .ok_or(format!(
+            "{}: expected a list of compile commands",
+            path.display()
+        ))?
+        .iter()
+        .map(parse_entry)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(
+            split_command(r#"cc -DNAME="a b" -I'my dir' 'x.c'"#),
+            words(&["cc", "-DNAME=a b", "-Imy dir", "x.c"])
+        );
+        assert_eq!(split_command(r#"cc -D'' """#), words(&["cc", "-D", ""]));
+    }
+
+    #[test]
+    fn split_escaped() {
+        assert_eq!(
+            split_command(r#"cc -DQ=\"x\" my\ file.c"#),
+            words(&["cc", "-DQ=\"x\"", "my file.c"])
+        );
+        assert_eq!(
+            split_command(r#"cc "-DP=\"a\\b\"" '-DS=\n'"#),
+            words(&["cc", "-DP=\"a\\b\"", "-DS=\\n"])
+        );
+    }
+
+    #[test]
+    fn flags_drop_output_and_compile() {
+        let argv = words(&["cc", "-c", "-o", "x.o", "-Iinc", "-ox2.o", "x.c", "-DX"]);
+        assert_eq!(parse_flags(&argv, "/src", "x.c"), words(&["-Iinc", "-DX"]));
+    }
+
+    #[test]
+    fn flags_drop_source_however_spelled() {
+        let argv = words(&["cc", "-c", "lib/x.c", "-Wall"]);
+        assert_eq!(
+            parse_flags(&argv, "/src", "/src/lib/x.c"),
+            words(&["-Wall"])
+        );
+
+        let argv = words(&["cc", "-c", "/src/lib/x.c", "./lib/y.c"]);
+        assert_eq!(parse_flags(&argv, "/src", "lib/x.c"), words(&["./lib/y.c"]));
+        assert_eq!(
+            parse_flags(&argv, "/src", "./lib/y.c"),
+            words(&["/src/lib/x.c"])
+        );
+    }
+}
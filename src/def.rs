@@ -1,11 +1,79 @@
+use std::fmt;
 
 type Label = String;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: u64,
+    pub col: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub begin: Location,
+    pub end: Location,
+}
+
+/* How a pointer that does not own its target may be used */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Borrow {
+    #[default]
+    None, /* not annotated */
+    Shared,  /* BORROW */
+    Mutable, /* BORROW_MUT */
+}
+
+/* A C type with typedefs resolved; `is_const` qualifies this level only */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub kind: TypeKind,
+    pub is_const: bool,
+    pub typedef: Option<String>, /* the typedef it was spelled with */
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeKind {
+    Void,
+    Scalar(String), /* builtins, enums and typedefs we know nothing about */
+    Record(String), /* struct or union, by tag */
+    Pointer(Box<Type>),
+    Array(Box<Type>),
+    Function(Box<Type>, Vec<Type>), /* return type, parameters */
+}
+
+impl Type {
+    pub fn new(kind: TypeKind) -> Type {
+        Type {
+            kind,
+            is_const: false,
+            typedef: None,
+        }
+    }
+
+    /* What a pointer points to, or the element of an array */
+    pub fn pointee(&self) -> Option<&Type> {
+        match &self.kind {
+            TypeKind::Pointer(ty) | TypeKind::Array(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /* The struct it is or points to */
+    pub fn record(&self) -> Option<&str> {
+        match &self.kind {
+            TypeKind::Record(name) => Some(name),
+            _ => self.pointee()?.record(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Variable {
-    pub mutable: bool,
+    pub ty: Type,
     pub ownership: bool,
-    pub indirection: u32,
+    pub borrow: Borrow,
+    pub derived_from: Option<usize>, /* returned borrow: the parameter it points into */
 }
 
 pub struct TranslationUnit {
@@ -13,41 +81,169 @@ pub struct TranslationUnit {
 }
 
 pub enum TranslationUnitSet {
-    Function(Function),
-    /* TODO: and declarations */
+    Function(Box<Function>),
+    Record(Record),
+}
+
+/* A struct or union definition; fields without an annotation are not owned */
+#[derive(Clone)]
+pub struct Record {
+    pub name: String,
+    pub fields: Vec<(String, Variable)>,
 }
 
 #[derive(Clone)]
 pub struct Function {
     pub name: String,
-    pub param: Vec<Variable>,
+    pub span: Span,
+    pub param: Vec<(String, Variable)>,
     pub ret_val: Option<Variable>,
-    pub inst: Vec<Inst>,
+    pub drop: bool,              /* annotated OWNERSHIP_DROP: releases its arguments */
+    pub inst: Vec<Inst>,         /* parameter declarations, then the body */
+    pub body_span: Option<Span>, /* None for a prototype */
 }
 
 #[derive(Clone)]
 
 pub enum Inst {
-    InstSet(u64, Vec<Inst>), // CompoundStmt
-    ParamDecl(u64, String, Variable),
-    FieldDecl(u64, String, Variable),
-    VarDecl(u64, String, Variable),
-    Assign(u64, String, ExprDescriptor),
-    Eval(u64, ExprDescriptor),
-    If(u64, Vec<Inst>), // must be size of 3
-    ReturnStmt(u64, String),
+    InstSet(Span, Vec<Inst>), // CompoundStmt
+    ParamDecl(Span, String, Variable),
+    FieldDecl(Span, String, Variable),
+    VarDecl(Span, String, Variable),
+    Assign(Span, Place, ExprDescriptor),
+    Eval(Span, ExprDescriptor),
+    If(Span, Vec<Inst>),      // cond, then and optional else
+    While(Span, Vec<Inst>),   // cond, body
+    DoWhile(Span, Vec<Inst>), // body, cond
+    For(Span, Vec<Inst>),     // init, cond, inc, body
+    Switch(Span, Vec<Inst>),  // cond, body
+    Case(Span, Vec<Inst>),    // labelled statement
+    Default(Span, Vec<Inst>), // labelled statement
+    Break(Span),
+    Continue(Span),
+    Label(Span, String, Vec<Inst>), // label id, labelled statement
+    Goto(Span, String),             // label id
+    EndScope(Span, Vec<String>),    // variables going out of scope
+    ReturnStmt(Span, Option<ExprDescriptor>), // returned value
+}
+
+impl Inst {
+    pub fn span(&self) -> &Span {
+        match self {
+            Inst::InstSet(span, _)
+            | Inst::ParamDecl(span, _, _)
+            | Inst::FieldDecl(span, _, _)
+            | Inst::VarDecl(span, _, _)
+            | Inst::Assign(span, _, _)
+            | Inst::Eval(span, _)
+            | Inst::If(span, _)
+            | Inst::While(span, _)
+            | Inst::DoWhile(span, _)
+            | Inst::For(span, _)
+            | Inst::Switch(span, _)
+            | Inst::Case(span, _)
+            | Inst::Default(span, _)
+            | Inst::Break(span)
+            | Inst::Continue(span)
+            | Inst::Label(span, _, _)
+            | Inst::Goto(span, _)
+            | Inst::EndScope(span, _)
+            | Inst::ReturnStmt(span, _) => span,
+        }
+    }
+}
+
+/* A step from a place into part of it */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Projection {
+    Field(String), /* `.a`; `p->a` is `(*p).a` */
+    Deref,         /* `*p` */
+    Index,         /* `[i]`; elements are not told apart */
+}
+
+/* Something that can be assigned to or moved from: a variable or part of one */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Place {
+    pub base: String,
+    pub projection: Vec<Projection>, /* outermost first */
+}
+
+impl Place {
+    pub fn variable(name: &str) -> Place {
+        Place {
+            base: name.to_string(),
+            projection: Vec::new(),
+        }
+    }
+
+    pub fn project(&self, projection: Projection) -> Place {
+        let mut place = self.clone();
+        place.projection.push(projection);
+        place
+    }
+
+    /* The place this one is part of */
+    pub fn parent(&self) -> Option<Place> {
+        let mut place = self.clone();
+        place.projection.pop()?;
+        Some(place)
+    }
+
+    /* Strictly inside `other`, like `x->a` is inside `x` */
+    pub fn is_within(&self, other: &Place) -> bool {
+        self.base == other.base
+            && self.projection.len() > other.projection.len()
+            && self.projection.starts_with(&other.projection)
+    }
+
+    /* One contains the other, so using one touches the other */
+    pub fn overlaps(&self, other: &Place) -> bool {
+        self == other || self.is_within(other) || other.is_within(self)
+    }
+}
+
+/* Spelled the way it would be in C */
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut text = self.base.clone();
+        let mut projection = self.projection.iter().peekable();
+        while let Some(step) = projection.next() {
+            text = match (step, projection.peek()) {
+                (Projection::Deref, Some(Projection::Field(field))) => {
+                    projection.next();
+                    format!("{}->{}", text, field)
+                }
+                (Projection::Deref, Some(_)) => format!("(*{})", text),
+                (Projection::Deref, None) => format!("*{}", text),
+                (Projection::Field(field), _) => format!("{}.{}", text, field),
+                (Projection::Index, _) => format!("{}[_]", text),
+            };
+        }
+        write!(f, "{}", text)
+    }
 }
 
 #[derive(Clone)]
 pub enum ExprDescriptor {
     FunctionCall(
+        Span,
         String,              /* func name */
         Vec<ExprDescriptor>, /* func args */
     ),
-    LocalVariable(String),
+    Place(Span, Place),
+    Literal(Span, String),      /* spelling of a constant */
+    AddressOf(Span, Place),     /* `&place` */
+    Temporary(Span, Vec<Inst>), /* evaluates the insts, then a value nobody owns */
 }
 
-pub enum ExprResult {
-    DeclaredVariable(String),
-    TemporaryVariable(bool),
-}
\ No newline at end of file
+impl ExprDescriptor {
+    pub fn span(&self) -> &Span {
+        match self {
+            ExprDescriptor::FunctionCall(span, _, _)
+            | ExprDescriptor::Place(span, _)
+            | ExprDescriptor::Literal(span, _)
+            | ExprDescriptor::AddressOf(span, _)
+            | ExprDescriptor::Temporary(span, _) => span,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::def::Span;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    UnknownFunction,
+    UnknownVariable,
+    UnsupportedSyntax,
+    UnreadableAnnotation,
+    UseAfterMove,
+    BorrowedToMove,
+    TemporaryNotOwned,
+    ConstToMutable,
+    Leak,
+    UseAfterFree,
+    DoubleFree,
+    DanglingReturn,
+    BorrowConflict,
+    DanglingBorrow,
+    FreeNotOwned,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnknownFunction => "W0001",
+            Code::UnknownVariable => "W0002",
+            Code::UnsupportedSyntax => "W0003",
+            Code::UnreadableAnnotation => "W0004",
+            /* E0001 was the forked path check; codes are never reused */
+            Code::UseAfterMove => "E0002",
+            Code::BorrowedToMove => "E0003",
+            Code::TemporaryNotOwned => "E0004",
+            Code::ConstToMutable => "E0005",
+            Code::Leak => "E0006",
+            Code::UseAfterFree => "E0007",
+            Code::DoubleFree => "E0008",
+            Code::DanglingReturn => "E0009",
+            Code::BorrowConflict => "E0010",
+            Code::DanglingBorrow => "E0011",
+            Code::FreeNotOwned => "E0012",
+        }
+    }
+}
+
+/* Secondary location, e.g. "value moved here" */
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: Code, message: String, span: &Span) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            span: span.clone(),
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn error(code: Code, message: String, span: &Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message, span)
+    }
+
+    pub fn warning(code: Code, message: String, span: &Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, message, span)
+    }
+
+    pub fn with_label(mut self, span: &Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span: span.clone(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /* Spans the AST left without a location fall back to the file being checked */
+    pub fn in_file(mut self, file: &str) -> Diagnostic {
+        let spans =
+            std::iter::once(&mut self.span).chain(self.labels.iter_mut().map(|l| &mut l.span));
+        for span in spans {
+            if span.begin.file.is_empty() {
+                span.begin.file = file.to_string();
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+fn write_span(f: &mut fmt::Formatter, span: &Span) -> fmt::Result {
+    let begin = &span.begin;
+    if begin.file.is_empty() {
+        write!(f, "<unknown>")
+    } else if begin.line == 0 {
+        write!(f, "{}", begin.file)
+    } else {
+        write!(f, "{}:{}:{}", begin.file, begin.line, begin.col)
+    }
+}
+
+/* Rendered like a compiler diagnostic so editors and CI logs pick it up */
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_span(f, &self.span)?;
+        write!(
+            f,
+            ": {}[{}]: {}",
+            self.severity,
+            self.code.as_str(),
+            self.message
+        )?;
+
+        for label in self.labels.iter() {
+            writeln!(f)?;
+            write!(f, "  ")?;
+            write_span(f, &label.span)?;
+            write!(f, ": {}: {}", Severity::Note, label.message)?;
+        }
+
+        Ok(())
+    }
+}
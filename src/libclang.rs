@@ -0,0 +1,340 @@
+use clang::diagnostic::Severity;
+use clang::source::SourceLocation;
+use clang::{Clang, Entity, EntityKind, Index, TypeKind};
+
+use serde_json::{json, Map, Value};
+
+use std::path::Path;
+
+/*
+ * Parse a C file in-process and present the cursor tree in the same shape as
+ * `clang -Xclang -ast-dump=json`, so `traverse_json` lowers both frontends into
+ * the same IR. Locations are always spelled out in full and annotation
+ * attributes carry their text, so nothing needs to be re-read from disk.
+ */
+pub fn parse(path: &Path, args: &[String]) -> Result<Value, String> {
+    let clang = Clang::new()?;
+    let index = Index::new(&clang, false, false);
+    let tu = index
+        .parser(path)
+        .arguments(args)
+        .parse()
+        .map_err(|e| e.to_string())?;
+
+    let errors: Vec<String> = tu
+        .get_diagnostics()
+        .iter()
+        .filter(|d| matches!(d.get_severity(), Severity::Error | Severity::Fatal))
+        .map(|d| d.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    Ok(to_value(tu.get_entity()))
+}
+
+/* Map a libclang cursor kind onto the node kind used by the JSON dump */
+fn kind_name(kind: EntityKind) -> String {
+    let name = match kind {
+        EntityKind::TranslationUnit => "TranslationUnitDecl",
+        EntityKind::ParmDecl => "ParmVarDecl",
+        EntityKind::StructDecl | EntityKind::UnionDecl => "RecordDecl",
+        EntityKind::MemberRefExpr => "MemberExpr",
+        EntityKind::UnaryExpr => "UnaryExprOrTypeTraitExpr",
+        _ => return format!("{:?}", kind),
+    };
+    name.to_string()
+}
+
+/*
+ * Where the initializer of a variable starts: the first `=` outside any
+ * brackets, so that `int a[N] = {0}` is not cut at a `=` inside `N`
+ */
+fn initializer_offset(entity: &Entity) -> Option<usize> {
+    let mut depth = 0;
+    for token in entity.get_range()?.tokenize() {
+        match token.get_spelling().as_str() {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            "=" if depth == 0 => {
+                return Some(token.get_location().get_file_location().offset as usize)
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/*
+ * TypeRef and friends have no counterpart in the JSON dump, and neither has
+ * anything libclang finds in the type of a declaration: array sizes, bit-field
+ * widths and the parameters of a function pointer. Of the attributes only the
+ * annotations matter.
+ */
+fn is_dumped(parent: &Entity, entity: &Entity) -> bool {
+    if entity.is_reference()
+        || entity.is_attribute() && entity.get_kind() != EntityKind::AnnotateAttr
+    {
+        return false;
+    }
+
+    let in_declarator = |entity: &Entity| match parent.get_kind() {
+        EntityKind::VarDecl => {
+            let start = entity
+                .get_range()
+                .map(|range| range.get_start().get_file_location().offset as usize);
+            match (start, initializer_offset(parent)) {
+                (Some(start), Some(init)) => start < init,
+                _ => true,
+            }
+        }
+        EntityKind::FieldDecl | EntityKind::ParmDecl => true,
+        _ => false,
+    };
+    match entity.get_kind() {
+        EntityKind::ParmDecl => parent.get_kind() == EntityKind::FunctionDecl,
+        _ if entity.is_expression() => !in_declarator(entity),
+        _ => true,
+    }
+}
+
+fn location(loc: SourceLocation) -> Value {
+    let loc = loc.get_spelling_location();
+    let mut map = Map::new();
+    map.insert("offset".to_string(), json!(loc.offset));
+    if let Some(file) = loc.file {
+        map.insert("file".to_string(), json!(file.get_path()));
+    }
+    map.insert("line".to_string(), json!(loc.line));
+    map.insert("col".to_string(), json!(loc.column));
+    Value::Object(map)
+}
+
+/*
+ * Macro expansions keep both locations, like the JSON dump does. The file
+ * location stands in for the expansion location so that macro arguments
+ * point at the argument rather than the macro name.
+ */
+fn full_location(loc: SourceLocation) -> Value {
+    let spelling = loc.get_spelling_location();
+    let expansion = loc.get_file_location();
+    if spelling.offset == expansion.offset && spelling.file == expansion.file {
+        return location(loc);
+    }
+
+    let mut expansion_loc = Map::new();
+    expansion_loc.insert("offset".to_string(), json!(expansion.offset));
+    if let Some(file) = expansion.file {
+        expansion_loc.insert("file".to_string(), json!(file.get_path()));
+    }
+    expansion_loc.insert("line".to_string(), json!(expansion.line));
+    expansion_loc.insert("col".to_string(), json!(expansion.column));
+
+    json!({
+        "spellingLoc": location(loc),
+        "expansionLoc": Value::Object(expansion_loc),
+    })
+}
+
+/* libclang has no accessor for the operator, so find the token after the lhs */
+fn binary_opcode(entity: &Entity) -> Option<String> {
+    let lhs = entity.get_children().into_iter().next()?;
+    let lhs_len = lhs.get_range()?.tokenize().len();
+    let tokens = entity.get_range()?.tokenize();
+    Some(tokens.get(lhs_len)?.get_spelling())
+}
+
+/*
+ * The JSON dump keeps a slot for each part of a `for`, with `{}` for the
+ * missing ones, while libclang only lists what is there. Put each child back
+ * in its slot by where it starts relative to the `;`s and the closing `)`.
+ */
+fn for_slots(entity: &Entity, children: Vec<Entity>) -> Vec<Value> {
+    let offset = |loc: SourceLocation| loc.get_file_location().offset;
+
+    let mut depth = 0;
+    let mut bounds = Vec::new();
+    if let Some(range) = entity.get_range() {
+        for token in range.tokenize() {
+            match token.get_spelling().as_str() {
+                "(" => depth += 1,
+                ")" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        bounds.push(offset(token.get_location()));
+                        break;
+                    }
+                }
+                ";" if depth == 1 => bounds.push(offset(token.get_location())),
+                _ => {}
+            }
+        }
+    }
+
+    /* init, condition variable, cond, inc, body */
+    let mut slots = vec![json!({}); 5];
+    for child in children {
+        let start = child
+            .get_range()
+            .map(|range| offset(range.get_start()))
+            .unwrap_or_default();
+        let slot = match bounds.iter().position(|&bound| start < bound) {
+            Some(0) => 0,
+            Some(1) => 2,
+            Some(2) => 3,
+            _ => 4,
+        };
+        slots[slot] = to_value(child);
+    }
+
+    slots
+}
+
+/* The operator is the first token, or the last one for `i++` and `i--` */
+fn unary_opcode(entity: &Entity) -> Option<String> {
+    let operand = entity.get_children().into_iter().next()?;
+    let tokens = entity.get_range()?.tokenize();
+    let postfix = operand.get_range()?.get_start() == entity.get_range()?.get_start();
+    let token = if postfix {
+        tokens.last()
+    } else {
+        tokens.first()
+    };
+    Some(token?.get_spelling())
+}
+
+/*
+ * Implicit casts are unexposed in libclang. The one that matters is an array
+ * used as a pointer, told by the types on either side.
+ */
+fn is_array_decay(entity: &Entity) -> bool {
+    let is_array = |ty: clang::Type| {
+        matches!(
+            ty.get_kind(),
+            TypeKind::ConstantArray | TypeKind::IncompleteArray | TypeKind::VariableArray
+        )
+    };
+    let children = entity.get_children();
+    entity.get_kind() == EntityKind::UnexposedExpr
+        && entity
+            .get_type()
+            .is_some_and(|ty| ty.get_kind() == TypeKind::Pointer)
+        && children.len() == 1
+        && children[0].get_type().is_some_and(is_array)
+}
+
+fn to_value(entity: Entity) -> Value {
+    let mut map = Map::new();
+
+    let kind = entity.get_kind();
+    map.insert("kind".to_string(), json!(kind_name(kind)));
+    if is_array_decay(&entity) {
+        map.insert("kind".to_string(), json!("ImplicitCastExpr"));
+        map.insert("castKind".to_string(), json!("ArrayToPointerDecay"));
+    }
+
+    if let Some(loc) = entity.get_location() {
+        map.insert("loc".to_string(), full_location(loc));
+    }
+    if let Some(range) = entity.get_range() {
+        map.insert(
+            "range".to_string(),
+            json!({
+                "begin": full_location(range.get_start()),
+                "end": full_location(range.get_end()),
+            }),
+        );
+    }
+    if let Some(name) = entity.get_name() {
+        if kind != EntityKind::AnnotateAttr {
+            map.insert("name".to_string(), json!(name));
+        }
+    }
+    /* Like the JSON dump, a typedef carries the type it stands for */
+    let ty = match kind {
+        EntityKind::TypedefDecl => entity.get_typedef_underlying_type(),
+        _ => entity.get_type(),
+    };
+    if let Some(ty) = ty {
+        map.insert(
+            "type".to_string(),
+            json!({ "qualType": ty.get_display_name() }),
+        );
+    }
+
+    match kind {
+        EntityKind::AnnotateAttr => {
+            map.insert("annotation".to_string(), json!(entity.get_display_name()));
+        }
+        EntityKind::DeclRefExpr => {
+            if let Some(decl) = entity.get_reference() {
+                map.insert(
+                    "referencedDecl".to_string(),
+                    json!({
+                        "kind": kind_name(decl.get_kind()),
+                        "name": decl.get_name(),
+                        "type": { "qualType": decl.get_type().map(|t| t.get_display_name()) },
+                    }),
+                );
+            }
+        }
+        EntityKind::UnaryOperator => {
+            map.insert("opcode".to_string(), json!(unary_opcode(&entity)));
+        }
+        EntityKind::BinaryOperator | EntityKind::CompoundAssignOperator => {
+            map.insert("opcode".to_string(), json!(binary_opcode(&entity)));
+        }
+        /* The member name is the last token, right after `->` or `.` */
+        EntityKind::MemberRefExpr => {
+            let is_arrow = entity.get_range().is_some_and(|range| {
+                let tokens = range.tokenize();
+                tokens.len() >= 2 && tokens[tokens.len() - 2].get_spelling() == "->"
+            });
+            map.insert("isArrow".to_string(), json!(is_arrow));
+        }
+        EntityKind::IntegerLiteral => {
+            let value = entity
+                .get_range()
+                .and_then(|range| range.tokenize().first().map(|token| token.get_spelling()));
+            map.insert("value".to_string(), json!(value));
+        }
+        /* Labels are unique within a function, so the name serves as the id */
+        EntityKind::LabelStmt => {
+            map.insert("declId".to_string(), json!(entity.get_name()));
+        }
+        EntityKind::GotoStmt => {
+            let label = entity
+                .get_children()
+                .into_iter()
+                .find(|child| child.get_kind() == EntityKind::LabelRef)
+                .and_then(|label| label.get_name());
+            map.insert("targetLabelDeclId".to_string(), json!(label));
+        }
+        EntityKind::IfStmt => {
+            map.insert(
+                "hasElse".to_string(),
+                json!(entity.get_children().len() == 3),
+            );
+        }
+        _ => {}
+    }
+
+    /* The JSON dump lists attributes after the rest of a declaration */
+    let (attrs, children): (Vec<Entity>, Vec<Entity>) = entity
+        .get_children()
+        .into_iter()
+        .filter(|child| is_dumped(&entity, child))
+        .partition(|child| child.get_kind() == EntityKind::AnnotateAttr);
+
+    let inner: Vec<Value> = if kind == EntityKind::ForStmt {
+        for_slots(&entity, children)
+    } else {
+        children.into_iter().chain(attrs).map(to_value).collect()
+    };
+    if !inner.is_empty() {
+        map.insert("inner".to_string(), Value::Array(inner));
+    }
+
+    Value::Object(map)
+}
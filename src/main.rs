@@ -1,54 +1,28 @@
 use regex::Regex;
 
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 
 use serde_json::Value;
 use std::fs;
 
+mod cfg;
+mod compile_commands;
 mod def;
+mod diagnostic;
+mod libclang;
+mod qual_type;
 mod verify;
 
 use def::*;
+use diagnostic::*;
 use verify::*;
 
-fn parse_variable(value: String) -> (bool, u32, String)
-/* mutability, indirection, name */ {
-    let is_const = value.matches("const").count();
-    let indirection = value.matches("*").count();
-
-    let parts = value.split(" ");
-    let name = parts.last();
-
-    // mutability and indirection
-    (is_const != 1, indirection as u32, name.unwrap().to_string())
-}
-
-fn parse_decl_stmt(state: &mut ExecutionState) {
-    /* Pop DeclStmt */
-    /*
-    for k in 0..state.var_decl {
-        /* Pop variable declaration */
-        let value = state.cmd.pop().unwrap();
-
-        /* Parse variable */
-        let (mutable, indirection, label) = parse_variable(value);
-
-        let ownership = true;
-
-        let var = Variable {
-            mutable,
-            ownership,
-            indirection,
-        };
-        state.variables.insert(label, var);
-    }
-
-    state.var_decl = 0;
-    */
-
-    // println!("{:?}", state.variables);
+/* The declared type of a node, with typedefs seen so far resolved */
+fn node_type(state: &ExecutionState, map: &serde_json::Map<std::string::String, Value>) -> Type {
+    let qual_type = map.get("type").and_then(get_qual_type).unwrap_or("");
+    qual_type::parse(qual_type, &state.typedefs)
 }
 
 fn split(s: String) -> (String, String) {
@@ -58,54 +32,120 @@ fn split(s: String) -> (String, String) {
     (label.to_string(), value.to_string())
 }
 
-fn post_ParmVarDecl(state: &mut ExecutionState, map: &serde_json::Map<std::string::String, Value>) {
-    /* Parse ownership */
-    let name = map.get("name").unwrap().as_str().unwrap().to_string();
-    let qual_type = get_qual_type(map.get("type").unwrap()).unwrap();
+fn has_annotation(state: &ExecutionState, annotation: &str) -> bool {
+    state.annotations.iter().any(|a| a == annotation)
+}
+
+const OWNERSHIP_ANNOTATIONS: [&str; 3] = ["MOVE", "BORROW", "BORROW_MUT"];
+
+/* What a typedef, or the struct a pointer points to, was annotated with */
+fn type_annotations(state: &ExecutionState, ty: &Type) -> Vec<String> {
+    let by_typedef = ty
+        .typedef
+        .as_ref()
+        .and_then(|name| state.typedef_annotations.get(name));
+    let by_record = match &ty.kind {
+        TypeKind::Pointer(pointee) => match &pointee.kind {
+            TypeKind::Record(tag) => state.record_annotations.get(tag),
+            _ => None,
+        },
+        _ => None,
+    };
+    by_typedef.or(by_record).cloned().unwrap_or_default()
+}
+
+/* A declaration without MOVE or BORROW of its own takes the one of its type */
+fn inherit_annotations(state: &mut ExecutionState, ty: &Type) {
+    if OWNERSHIP_ANNOTATIONS
+        .iter()
+        .any(|annotation| has_annotation(state, annotation))
+    {
+        return;
+    }
+    let inherited = type_annotations(state, ty);
+    state.annotations.extend(inherited);
+}
 
-    let ownership = state.annotation.contains("MOVE");
-    state.annotation.clear();
+/* Only the ownership annotations are kept for a type */
+fn take_ownership_annotations(state: &mut ExecutionState) -> Vec<String> {
+    state
+        .annotations
+        .drain(..)
+        .filter(|annotation| OWNERSHIP_ANNOTATIONS.contains(&annotation.as_str()))
+        .collect()
+}
 
-    // println!("{} {}", name, qual_type);
+fn borrow_annotation(state: &ExecutionState) -> Borrow {
+    if has_annotation(state, "BORROW_MUT") {
+        Borrow::Mutable
+    } else if has_annotation(state, "BORROW") {
+        Borrow::Shared
+    } else {
+        Borrow::None
+    }
+}
 
-    let is_const = qual_type.matches("const").count();
-    let indirection = qual_type.matches("*").count();
+fn post_ParmVarDecl(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    span: &Span,
+) {
+    /* Parse ownership; prototypes may leave parameters unnamed */
+    let name = map
+        .get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
+    let ty = node_type(state, map);
+    inherit_annotations(state, &ty);
+
+    let ownership = has_annotation(state, "MOVE");
+    let borrow = borrow_annotation(state);
+    /* By position, as prototypes may leave it unnamed; the earlier parameters are on top */
+    if has_annotation(state, "RETURN_BORROW") {
+        let index = state
+            .inst
+            .iter()
+            .rev()
+            .take_while(|inst| matches!(inst, Inst::ParamDecl(..)))
+            .count();
+        state.return_borrow = Some(index);
+    }
+    state.annotations.clear();
 
     let variable = Variable {
-        mutable: is_const == 0,
+        ty,
         ownership,
-        indirection: indirection as u32,
+        borrow,
+        derived_from: None,
     };
 
-    let inst = Inst::ParamDecl(0, name, variable);
+    let inst = Inst::ParamDecl(span.clone(), name, variable);
     state.inst.push(inst);
 }
 
-fn post_FieldDecl(state: &mut ExecutionState, map: &serde_json::Map<std::string::String, Value>) {
+fn post_FieldDecl(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    span: &Span,
+) {
     /* Parse ownership */
-    assert!(
-        state.annotation.len() != 0,
-        "FieldDecl ownership must be annotated!"
-    );
-
-    let ownership = state.annotation.contains("MOVE");
-    state.annotation.clear();
+    let ty = node_type(state, map);
+    inherit_annotations(state, &ty);
+    let ownership = has_annotation(state, "MOVE");
+    let borrow = borrow_annotation(state);
+    state.annotations.clear();
 
     let name = map.get("name").unwrap().as_str().unwrap().to_string();
-    let qual_type = get_qual_type(map.get("type").unwrap()).unwrap();
-
-    println!("{} {}", name, qual_type);
-
-    let is_const = qual_type.matches("const").count();
-    let indirection = qual_type.matches("*").count();
 
     let variable = Variable {
-        mutable: is_const == 0,
+        ty,
         ownership,
-        indirection: indirection as u32,
+        borrow,
+        derived_from: None,
     };
 
-    let inst = Inst::FieldDecl(0, name, variable);
+    let inst = Inst::FieldDecl(span.clone(), name, variable);
     state.inst.push(inst);
 }
 
@@ -123,39 +163,34 @@ fn parse_unexposed_expr(state: &mut ExecutionState) {
     /* No side effects */
 }
 
-fn post_process_CallExpr(state: &mut ExecutionState, children: u32) {
-    let mut args = Vec::new();
-    for k in 0..children - 1 {
-        let inst = state.inst.pop().unwrap();
-        match inst {
-            Inst::VarDecl(0, label, variable) => {
-                args.push(ExprDescriptor::LocalVariable(label));
-            }
-            Inst::Eval(0, expr) => match expr {
-                ExprDescriptor::FunctionCall(label, aargs) => {
-                    args.push(ExprDescriptor::FunctionCall(label, aargs));
-                }
-                _ => {
-                    unreachable!();
-                }
-            },
-            _ => {
-                unreachable!();
-            }
-        }
-    }
-    args.reverse();
-    let func = state.inst.pop().unwrap();
-
-    match func {
-        Inst::VarDecl(0, label, variable) => {
-            let inst = Inst::Eval(0, ExprDescriptor::FunctionCall(label, args));
-            state.inst.push(inst);
-        }
-        _ => {
-            unreachable!();
-        }
+fn post_process_CallExpr(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut steps = Vec::new();
+    let mut children = Vec::new();
+    for child in pop_children(state, child_insts, span) {
+        let (child_steps, child) = split_operand(as_condition(child));
+        steps.extend(child_steps);
+        children.push(child);
     }
+    let mut children = children.into_iter();
+    let callee = children.next();
+
+    /* Operators and the like are evaluated for what they read, then passed as a temporary */
+    let args = children
+        .map(|arg| match arg {
+            Inst::Eval(_, expr) => expr,
+            arg => ExprDescriptor::Temporary(arg.span().clone(), vec![arg]),
+        })
+        .collect();
+
+    /* Through a function pointer the callee is whatever it names */
+    let inst = match callee {
+        Some(Inst::Eval(_, ExprDescriptor::Place(_, place))) => Inst::Eval(
+            span.clone(),
+            ExprDescriptor::FunctionCall(span.clone(), place.to_string(), args),
+        ),
+        callee => temporary(span, callee.into_iter().collect()),
+    };
+    state.inst.push(after_steps(span, steps, inst));
 }
 
 fn split_function_signature(signature: &str) -> Option<(String, String, String)> {
@@ -174,101 +209,310 @@ fn split_function_signature(signature: &str) -> Option<(String, String, String)>
     }
 }
 
-fn remove_parentheses(s: &str) -> String {
-    // Define a regular expression that matches everything inside parentheses (inclusive)
-    let re = Regex::new(r"\([^)]*\)").unwrap();
-
-    // Replace all matches with an empty string
-    re.replace_all(s, "").to_string()
-}
-
 fn post_FunctionDecl(
     state: &mut ExecutionState,
     map: &serde_json::Map<std::string::String, Value>,
-    inst_cnt: usize,
+    child_insts: &[usize],
+    span: &Span,
 ) {
     let mut name: Option<&str> = None;
     let mut qual_type: Option<&str> = None;
 
     let name = map.get("name").unwrap().as_str().unwrap().to_string();
-    let qual_type = get_qual_type(map.get("type").unwrap()).unwrap();
-    let line = map.get("loc").unwrap().get("line").unwrap().as_str();
 
-    let ret_type = remove_parentheses(qual_type);
-    let is_const = ret_type.matches("const").count();
-    let indirection = ret_type.matches("*").count();
+    let ret_type = match node_type(state, map).kind {
+        TypeKind::Function(ret_type, _) => *ret_type,
+        _ => Type::new(TypeKind::Void),
+    };
+
+    /* Function attributes come after the body, so they are ours now */
+    inherit_annotations(state, &ret_type);
+    let ownership = has_annotation(state, "MOVE");
+    let drop = has_annotation(state, "OWNERSHIP_DROP");
+    let borrow = borrow_annotation(state);
+    state.annotations.clear();
 
     let mut return_type = None;
-    if ret_type.contains("void") && indirection == 0 {
-    } else {
+    if ret_type.kind != TypeKind::Void {
         return_type = Some(Variable {
-            ownership: false,
-            mutable: is_const == 0,
-            indirection: indirection as u32,
+            ty: ret_type,
+            ownership,
+            borrow,
+            derived_from: None,
         });
     }
 
-    let curr_size = state.inst.len();
-
-    let mut inst_set = Vec::new();
-    for k in 0..curr_size - inst_cnt {
-        let inst = state.inst.pop().unwrap();
-        inst_set.push(inst);
-    }
-    inst_set.reverse();
+    /* Parameters and the body; attributes and comments leave nothing to check */
+    let kinds = map
+        .get("inner")
+        .and_then(|inner| inner.as_array())
+        .into_iter()
+        .flatten()
+        .map(|child| child.get("kind").and_then(|kind| kind.as_str()));
+    let children = pop_children(state, child_insts, span);
 
     let mut inst = Vec::new();
     let mut param = Vec::new();
-    for k in inst_set.iter() {
-        match k {
-            Inst::ParamDecl(line, name, property) => {
-                param.push(property.clone());
+    let mut body_span = None;
+    for (kind, child) in kinds.zip(children) {
+        match (kind, child) {
+            (_, Inst::ParamDecl(param_span, name, property)) => {
+                param.push((name.clone(), property.clone()));
+                inst.push(Inst::ParamDecl(param_span, name, property));
             }
-            Inst::InstSet(line, set) => {
-                inst = set.clone();
-            }
-            _ => {
-                unreachable!();
+            (Some("CompoundStmt"), Inst::InstSet(body, set)) => {
+                inst.extend(set);
+                body_span = Some(body);
             }
+            _ => {}
+        }
+    }
+
+    /* A RETURN_BORROW parameter is where the returned pointer points into */
+    if let Some(source) = state.return_borrow.take() {
+        if let Some(ret_val) = return_type.as_mut() {
+            ret_val.derived_from = Some(source);
         }
     }
 
     state
         .tl
         .sub_unit
-        .push(TranslationUnitSet::Function(Function {
+        .push(TranslationUnitSet::Function(Box::new(Function {
             name,
+            span: span.clone(),
             param,
             ret_val: return_type,
+            drop,
             inst,
-        }));
+            body_span,
+        })));
+}
+
+/* A forward declaration has no fields and does not define the struct */
+fn post_RecordDecl(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    inst_cnt: usize,
+) {
+    let mut annotations = state.pending_record_annotations.pop().unwrap_or_default();
+    annotations.append(&mut take_ownership_annotations(state));
+
+    let fields: Vec<(String, Variable)> = state
+        .inst
+        .split_off(inst_cnt)
+        .into_iter()
+        .filter_map(|inst| match inst {
+            Inst::FieldDecl(_, name, variable) => Some((name, variable)),
+            _ => None,
+        })
+        .collect();
+
+    let Some(name) = map.get("name").and_then(|v| v.as_str()) else {
+        return;
+    };
+    if !annotations.is_empty() {
+        state
+            .record_annotations
+            .insert(name.to_string(), annotations);
+    }
+    if map.get("inner").is_none() {
+        return;
+    }
+
+    state.tl.sub_unit.push(TranslationUnitSet::Record(Record {
+        name: name.to_string(),
+        fields,
+    }));
+}
+
+/* `x->a` is the field of what `x` points to */
+fn post_MemberExpr(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    child_insts: &[usize],
+    span: &Span,
+) {
+    let field = map.get("name").and_then(|v| v.as_str()).unwrap_or("");
+    let is_arrow = map
+        .get("isArrow")
+        .and_then(|v| v.as_bool())
+        .unwrap_or_default();
+    let (steps, base) = split_operand(pop_children(state, child_insts, span).pop().unwrap());
+
+    let record = if is_arrow {
+        pointee_place(&base)
+    } else {
+        as_place(&base)
+    };
+    let inst = match record {
+        Some(place) => place_inst(span, place.project(Projection::Field(field.to_string()))),
+        None => temporary(span, vec![as_condition(base)]),
+    };
+    state.inst.push(after_steps(span, steps, inst));
 }
 
-fn post_BinaryOperator(state: &mut ExecutionState, children: u32) {
-    /* Pop BinaryOperator */
-    // let _ = state.ast.pop();
+/*
+ * Elements are not told apart, so the index does not change the place; what
+ * it reads is evaluated first
+ */
+fn post_ArraySubscriptExpr(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut operands = pop_children(state, child_insts, span);
+    let index = operands.pop().unwrap();
+    let (mut steps, base) = split_operand(operands.pop().unwrap());
+    if !matches!(index, Inst::Eval(_, ExprDescriptor::Literal(..))) {
+        steps.push(as_condition(index));
+    }
 
-    let rhs = state.inst.pop().unwrap();
-    let lhs = state.inst.pop().unwrap();
+    /* `(&x)[i]` is `x` itself; an element of a decayed array is one */
+    let element = match &base {
+        Inst::Eval(_, ExprDescriptor::AddressOf(_, place)) => Some(place.clone()),
+        base => as_place(base).map(|place| place.project(Projection::Index)),
+    };
+    let inst = match element {
+        Some(place) => place_inst(span, place),
+        None => temporary(span, vec![as_condition(base)]),
+    };
+    state.inst.push(after_steps(span, steps, inst));
+}
 
-    match (lhs, rhs) {
-        (Inst::VarDecl(line, label, variable), Inst::Eval(line2, expr)) => {
-            state.inst.push(Inst::Assign(0, label, expr));
-        }
-        _ => {
-            unreachable!();
+/* An array used as a value is the address of its first element */
+fn post_array_decay(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let (steps, array) = split_operand(pop_children(state, child_insts, span).pop().unwrap());
+    let inst = match as_place(&array) {
+        Some(place) => Inst::Eval(
+            span.clone(),
+            ExprDescriptor::AddressOf(span.clone(), place.project(Projection::Index)),
+        ),
+        None => array,
+    };
+    state.inst.push(after_steps(span, steps, inst));
+}
+
+fn post_BinaryOperator(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    child_insts: &[usize],
+    span: &Span,
+) {
+    let opcode = map.get("opcode").and_then(|v| v.as_str()).unwrap_or("");
+    let mut operands = pop_children(state, child_insts, span);
+    let rhs = operands.pop().unwrap();
+    let lhs = operands.pop().unwrap();
+
+    /* Arithmetic, comparisons and the like only read their operands */
+    if opcode != "=" {
+        state
+            .inst
+            .push(temporary(span, vec![as_condition(lhs), as_condition(rhs)]));
+        return;
+    }
+
+    let (mut steps, lhs) = split_operand(lhs);
+    let (rhs_steps, rhs) = split_operand(as_condition(rhs));
+    steps.extend(rhs_steps);
+    let inst = match (as_place(&lhs), rhs) {
+        (Some(place), Inst::Eval(_, expr)) => Inst::Assign(span.clone(), place, expr),
+        /* The place is overwritten, not read, with whatever the rhs comes to */
+        (Some(place), rhs) => Inst::Assign(
+            span.clone(),
+            place,
+            ExprDescriptor::Temporary(rhs.span().clone(), vec![rhs]),
+        ),
+        (None, rhs) => temporary(span, vec![as_condition(lhs), rhs]),
+    };
+    state.inst.push(after_steps(span, steps, inst));
+}
+
+/* `i++`, `!p` and friends only read their operand; `*p` names what `p` points to */
+fn post_UnaryOperator(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    child_insts: &[usize],
+    span: &Span,
+) {
+    let opcode = map.get("opcode").and_then(|v| v.as_str()).unwrap_or("");
+    let (steps, operand) = split_operand(pop_children(state, child_insts, span).pop().unwrap());
+
+    let inst = match (opcode, as_place(&operand), pointee_place(&operand)) {
+        ("&", Some(place), _) => {
+            Inst::Eval(span.clone(), ExprDescriptor::AddressOf(span.clone(), place))
         }
+        ("*", _, Some(place)) => place_inst(span, place),
+        _ => temporary(span, vec![as_condition(operand)]),
+    };
+    state.inst.push(after_steps(span, steps, inst));
+}
+
+/*
+ * The declaration was pushed before its children, so whatever they left is the
+ * initializer; annotations leave nothing. The variable gets its initial value
+ * by an assignment right after it is declared.
+ */
+fn post_VarDecl(state: &mut ExecutionState, inst_cnt: usize, span: &Span) {
+    if let Some(Inst::VarDecl(_, _, variable)) =
+        inst_cnt.checked_sub(1).and_then(|at| state.inst.get(at))
+    {
+        let ty = variable.ty.clone();
+        inherit_annotations(state, &ty);
     }
+    let ownership = has_annotation(state, "MOVE");
+    let borrow = borrow_annotation(state);
+    state.annotations.clear();
+
+    let mut init = state.inst.split_off(inst_cnt);
+    let Some(Inst::VarDecl(_, label, variable)) = inst_cnt
+        .checked_sub(1)
+        .and_then(|at| state.inst.get_mut(at))
+    else {
+        state.inst.append(&mut init);
+        return;
+    };
+    variable.ownership = ownership;
+    variable.borrow = borrow;
+    let place = Place::variable(label);
+
+    let init = match init.len() {
+        0 => return,
+        1 => init.pop().unwrap(),
+        _ => Inst::InstSet(span.clone(), init),
+    };
+    let (steps, init) = split_operand(as_condition(init));
+    state.inst.extend(steps);
+    let expr = match init {
+        Inst::Eval(_, expr) => expr,
+        init => ExprDescriptor::Temporary(init.span().clone(), vec![init]),
+    };
+    state.inst.push(Inst::Assign(span.clone(), place, expr));
 }
 
-fn post_VarDecl(state: &mut ExecutionState) {
-    /* Pop VarDecl */
-    // let (_, value) = split(state.ast.pop().unwrap());
+/* `{ a, b }` only reads its elements */
+fn post_InitListExpr(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let elements = pop_children(state, child_insts, span);
+    state.inst.push(temporary(
+        span,
+        elements.into_iter().map(as_condition).collect(),
+    ));
+}
 
-    // /* Push the raw string onto cmd for future process */
-    // state.cmd.push(value);
+/* The dump gives a typedef the type it stands for */
+fn post_TypedefDecl(state: &mut ExecutionState, map: &serde_json::Map<std::string::String, Value>) {
+    let Some(name) = map.get("name").and_then(|v| v.as_str()) else {
+        return;
+    };
+    let ty = node_type(state, map);
 
-    state.var_decl += 1;
+    /* Unless annotated itself, a typedef passes on what its type has */
+    let mut annotations = take_ownership_annotations(state);
+    if annotations.is_empty() {
+        annotations = type_annotations(state, &ty);
+    }
+    if !annotations.is_empty() {
+        state
+            .typedef_annotations
+            .insert(name.to_string(), annotations);
+    }
+    state.typedefs.insert(name.to_string(), ty);
 }
 
 fn post_attribute_annotate(state: &mut ExecutionState) {
@@ -277,7 +521,7 @@ fn post_attribute_annotate(state: &mut ExecutionState) {
     // state.annotation = v;
 }
 
-fn post_CompoundStmt(state: &mut ExecutionState, inst_cnt: usize) {
+fn post_CompoundStmt(state: &mut ExecutionState, inst_cnt: usize, span: &Span) {
     let curr_size = state.inst.len();
 
     let mut inst_set = Vec::new();
@@ -287,38 +531,232 @@ fn post_CompoundStmt(state: &mut ExecutionState, inst_cnt: usize) {
     }
     inst_set.reverse();
 
-    state.inst.push(Inst::InstSet(0, inst_set));
+    state.inst.push(Inst::InstSet(span.clone(), inst_set));
+}
+
+/*
+ * Exactly one inst per child. Children that lowered to nothing (literals,
+ * empty statements) become an empty block and several insts are grouped.
+ */
+fn pop_children(state: &mut ExecutionState, child_insts: &[usize], span: &Span) -> Vec<Inst> {
+    let mut children = Vec::new();
+    for count in child_insts.iter().rev() {
+        let at = state.inst.len() - count;
+        let mut insts = state.inst.split_off(at);
+        let inst = match insts.len() {
+            0 => Inst::InstSet(span.clone(), Vec::new()),
+            1 => insts.pop().unwrap(),
+            _ => Inst::InstSet(span.clone(), insts),
+        };
+        children.push(inst);
+    }
+    children.reverse();
+
+    children
+}
+
+/* Variables, fields, elements and dereferences name a place */
+fn as_place(inst: &Inst) -> Option<Place> {
+    match inst {
+        Inst::VarDecl(_, label, _) => Some(Place::variable(label)),
+        Inst::Eval(_, ExprDescriptor::Place(_, place)) => Some(place.clone()),
+        _ => None,
+    }
+}
+
+fn place_inst(span: &Span, place: Place) -> Inst {
+    Inst::Eval(span.clone(), ExprDescriptor::Place(span.clone(), place))
+}
+
+/* What a pointer operand points to; `*&x` is `x` itself */
+fn pointee_place(inst: &Inst) -> Option<Place> {
+    match inst {
+        Inst::Eval(_, ExprDescriptor::AddressOf(_, place)) => Some(place.clone()),
+        inst => Some(as_place(inst)?.project(Projection::Deref)),
+    }
+}
+
+/* A value computed from its operands, owned by nobody */
+fn temporary(span: &Span, operands: Vec<Inst>) -> Inst {
+    Inst::Eval(
+        span.clone(),
+        ExprDescriptor::Temporary(span.clone(), operands),
+    )
+}
+
+/*
+ * An operand that takes steps to evaluate, such as `a[i]`, is a block ending
+ * in its value. The steps are split off to go before whatever uses the value.
+ */
+fn split_operand(inst: Inst) -> (Vec<Inst>, Inst) {
+    match inst {
+        Inst::InstSet(_, mut insts) if matches!(insts.last(), Some(Inst::Eval(..))) => {
+            let value = insts.pop().unwrap();
+            (insts, value)
+        }
+        inst => (Vec::new(), inst),
+    }
+}
+
+fn after_steps(span: &Span, mut steps: Vec<Inst>, inst: Inst) -> Inst {
+    if steps.is_empty() {
+        return inst;
+    }
+    steps.push(inst);
+    Inst::InstSet(span.clone(), steps)
+}
+
+/* A condition or operand only reads the variables it names */
+fn as_condition(inst: Inst) -> Inst {
+    match inst {
+        Inst::VarDecl(span, label, _) => place_inst(&span, Place::variable(&label)),
+        Inst::InstSet(span, inst_set) => {
+            Inst::InstSet(span, inst_set.into_iter().map(as_condition).collect())
+        }
+        inst => inst,
+    }
+}
+
+fn post_IfStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut inst_set = pop_children(state, child_insts, span);
+    let cond = inst_set.remove(0);
+    inst_set.insert(0, as_condition(cond));
+
+    state.inst.push(Inst::If(span.clone(), inst_set));
+}
+
+fn post_WhileStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut inst_set = pop_children(state, child_insts, span);
+    let cond = inst_set.remove(0);
+    inst_set.insert(0, as_condition(cond));
+
+    state.inst.push(Inst::While(span.clone(), inst_set));
+}
+
+fn post_DoStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut inst_set = pop_children(state, child_insts, span);
+    let cond = inst_set.pop().unwrap();
+    inst_set.push(as_condition(cond));
+
+    state.inst.push(Inst::DoWhile(span.clone(), inst_set));
+}
+
+/* The dump always has five children: init, condition variable, cond, inc and body */
+fn post_ForStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut inst_set = pop_children(state, child_insts, span);
+    let body = inst_set.pop().unwrap();
+    let inc = inst_set.pop().unwrap();
+    let cond = inst_set.pop().unwrap();
+    let _cond_var = inst_set.pop();
+    let init = inst_set.pop().unwrap();
+
+    state.inst.push(Inst::For(
+        span.clone(),
+        vec![init, as_condition(cond), as_condition(inc), body],
+    ));
+}
+
+fn post_SwitchStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut inst_set = pop_children(state, child_insts, span);
+    let cond = inst_set.remove(0);
+    inst_set.insert(0, as_condition(cond));
+
+    state.inst.push(Inst::Switch(span.clone(), inst_set));
 }
 
-fn post_IfStmt(
+/* The case value is a constant; only the labelled statement is kept */
+fn post_CaseStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let stmt = pop_children(state, child_insts, span).pop().unwrap();
+    state.inst.push(Inst::Case(span.clone(), vec![stmt]));
+}
+
+fn post_DefaultStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let inst_set = pop_children(state, child_insts, span);
+    state.inst.push(Inst::Default(span.clone(), inst_set));
+}
+
+/* Gotos name their target by declaration id, so labels are keyed by it too */
+fn post_LabelStmt(
     state: &mut ExecutionState,
     map: &serde_json::Map<std::string::String, Value>,
-    inst_cnt: usize,
+    child_insts: &[usize],
+    span: &Span,
 ) {
-    let curr_size = state.inst.len();
-    let mut inst_set = Vec::new();
-    for k in 0..curr_size - inst_cnt {
-        let inst = state.inst.pop().unwrap();
-        inst_set.push(inst);
-    }
-    inst_set.reverse();
+    let id = map.get("declId").and_then(|v| v.as_str()).unwrap_or("");
+    let inst_set = pop_children(state, child_insts, span);
+    state
+        .inst
+        .push(Inst::Label(span.clone(), id.to_string(), inst_set));
+}
+
+fn post_GotoStmt(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    span: &Span,
+) {
+    let id = map
+        .get("targetLabelDeclId")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    state.inst.push(Inst::Goto(span.clone(), id.to_string()));
+}
+
+/* Integer, character, floating and string constants */
+fn post_literal(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    span: &Span,
+) {
+    let value = match map.get("value") {
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+        None => String::new(),
+    };
+    state.inst.push(Inst::Eval(
+        span.clone(),
+        ExprDescriptor::Literal(span.clone(), value),
+    ));
+}
 
-    // let range = map.get("range");
-    // let begin = range.unwrap().get("begin").unwrap();
-    // let line = begin.get("line").unwrap();
-    let line = map
-        .get("range")
-        .unwrap()
-        .get("begin")
-        .unwrap()
-        .get("line")
-        .unwrap();
-    state.inst.push(Inst::If(line.as_u64().unwrap_or_default(), inst_set));
+/* `sizeof x` does not evaluate `x` */
+fn post_UnaryExprOrTypeTraitExpr(state: &mut ExecutionState, inst_cnt: usize, span: &Span) {
+    state.inst.truncate(inst_cnt);
+    state.inst.push(Inst::Eval(
+        span.clone(),
+        ExprDescriptor::Literal(span.clone(), String::new()),
+    ));
 }
 
-fn post_ReturnStmt(state: &mut ExecutionState) {
-    /* TODO: return real variable */
-    state.inst.push(Inst::ReturnStmt(0, "".to_string()));
+/*
+ * Node kinds we know nothing about are checked for what their operands read
+ * and assign, and flagged so the gap does not go unnoticed.
+ */
+fn post_unsupported(state: &mut ExecutionState, kind: &str, child_insts: &[usize], span: &Span) {
+    let operands = pop_children(state, child_insts, span);
+    state.inst.push(temporary(
+        span,
+        operands.into_iter().map(as_condition).collect(),
+    ));
+    state.diagnostics.push(Diagnostic::warning(
+        Code::UnsupportedSyntax,
+        format!("`{}` is not supported; only its operands are checked", kind),
+        span,
+    ));
+}
+
+/* Anything more complex than a single expression is evaluated before returning */
+fn post_ReturnStmt(state: &mut ExecutionState, child_insts: &[usize], span: &Span) {
+    let mut value = None;
+    for inst in pop_children(state, child_insts, span) {
+        let (steps, inst) = split_operand(as_condition(inst));
+        state.inst.extend(steps);
+        match inst {
+            Inst::Eval(_, expr) => value = Some(expr),
+            inst => state.inst.push(inst),
+        }
+    }
+
+    state.inst.push(Inst::ReturnStmt(span.clone(), value));
 }
 
 fn parse_paren_expr(state: &mut ExecutionState) {
@@ -336,52 +774,43 @@ struct Component {}
 //     indirection: u32,
 // }
 
-// fn evaluate(expr: &ExprDescriptor) -> ExprResult {}
-
-fn evaluate(rhs: &ExprDescriptor) -> ExprResult {
-    match rhs {
-        ExprDescriptor::FunctionCall(func, params) => {
-            let mut args = Vec::new();
-            for k in 0..params.len() {
-                args.push(evaluate(&params[k]));
-            }
-
-            /* TODO: verify logic */
-
-            return ExprResult::TemporaryVariable(/* todo */ true);
-        }
-        ExprDescriptor::LocalVariable(var) => {
-            return ExprResult::DeclaredVariable(var.clone());
-        }
-    }
-}
-
 struct ExecutionState {
     // params: Option<Vec<Variable>>,
     // variables: HashMap<String, Variable>,
     // declared_functions: HashMap<String, Function>,
     depth: u32,
+    dump_ast: bool,
     // cmd: Vec<String>,
-    annotation: String,
-    var_decl: u32,
+    annotations: Vec<String>,
+    return_borrow: Option<usize>, /* parameter annotated RETURN_BORROW */
+    typedefs: HashMap<String, Type>,
+    typedef_annotations: HashMap<String, Vec<String>>, /* MOVE or BORROW on a typedef */
+    record_annotations: HashMap<String, Vec<String>>,  /* ... or on a struct, by tag */
+    pending_record_annotations: Vec<Vec<String>>,      /* records being walked */
+    source: PathBuf,
+    sources: HashMap<String, String>,
+    last_file: String,
+    last_line: u64,
     inst: Vec<Inst>,
     tl: TranslationUnit,
+    diagnostics: Vec<Diagnostic>, /* found while lowering */
 }
 
 fn post_processing(
     map: &serde_json::Map<std::string::String, Value>,
     state: &mut ExecutionState,
-    children: u32,
+    child_insts: &[usize],
     inst_cnt: usize,
+    span: &Span,
 ) {
     if let Some(kind) = map.get("kind") {
         if let Some(kind_str) = kind.as_str() {
             match kind_str {
                 "FunctionDecl" => {
-                    post_FunctionDecl(state, map, inst_cnt);
+                    post_FunctionDecl(state, map, child_insts, span);
                 }
                 "CallExpr" => {
-                    post_process_CallExpr(state, children);
+                    post_process_CallExpr(state, child_insts, span);
                 }
                 "UnexposedExpr" => {
                     parse_unexposed_expr(state);
@@ -390,49 +819,111 @@ fn post_processing(
                     post_DeclRefExpr(state);
                 }
                 "ParmVarDecl" => {
-                    post_ParmVarDecl(state, map);
+                    post_ParmVarDecl(state, map, span);
                 }
                 "FieldDecl" => {
-                    post_FieldDecl(state, map);
+                    post_FieldDecl(state, map, span);
+                }
+                "RecordDecl" => {
+                    post_RecordDecl(state, map, inst_cnt);
+                }
+                "MemberExpr" => {
+                    post_MemberExpr(state, map, child_insts, span);
+                }
+                "ArraySubscriptExpr" => {
+                    post_ArraySubscriptExpr(state, child_insts, span);
                 }
-                "DeclStmt" => {
-                    parse_decl_stmt(state);
+                /* Each declarator is lowered on its own, initializer included */
+                "DeclStmt" => {}
+                "BinaryOperator" | "CompoundAssignOperator" => {
+                    post_BinaryOperator(state, map, child_insts, span);
                 }
-                "BinaryOperator" => {
-                    post_BinaryOperator(state, children);
+                "UnaryOperator" => {
+                    post_UnaryOperator(state, map, child_insts, span);
                 }
                 "VarDecl" => {
-                    post_VarDecl(state);
+                    post_VarDecl(state, inst_cnt, span);
+                }
+                "InitListExpr" => {
+                    post_InitListExpr(state, child_insts, span);
                 }
                 "attribute(annotate)" => {
                     post_attribute_annotate(state);
                 }
                 "CompoundStmt" => {
-                    post_CompoundStmt(state, inst_cnt);
+                    post_CompoundStmt(state, inst_cnt, span);
                 }
                 "ParenExpr" => {
                     parse_paren_expr(state);
                 }
                 "IfStmt" => {
-                    post_IfStmt(state, map, inst_cnt);
+                    post_IfStmt(state, child_insts, span);
+                }
+                "WhileStmt" => {
+                    post_WhileStmt(state, child_insts, span);
+                }
+                "DoStmt" => {
+                    post_DoStmt(state, child_insts, span);
+                }
+                "ForStmt" => {
+                    post_ForStmt(state, child_insts, span);
+                }
+                "SwitchStmt" => {
+                    post_SwitchStmt(state, child_insts, span);
+                }
+                "CaseStmt" => {
+                    post_CaseStmt(state, child_insts, span);
+                }
+                "DefaultStmt" => {
+                    post_DefaultStmt(state, child_insts, span);
+                }
+                "BreakStmt" => {
+                    state.inst.push(Inst::Break(span.clone()));
+                }
+                "ContinueStmt" => {
+                    state.inst.push(Inst::Continue(span.clone()));
+                }
+                "LabelStmt" => {
+                    post_LabelStmt(state, map, child_insts, span);
+                }
+                "GotoStmt" => {
+                    post_GotoStmt(state, map, span);
                 }
                 "ReturnStmt" => {
-                    post_ReturnStmt(state);
-                }
-                "BuiltinType" => {}
-                "TypedefDecl" => {}
-                "RecordDecl" => {}
-                "RecordType" => {}
-                "PointerType" => {}
-                "ConstantArrayType" => {}
+                    post_ReturnStmt(state, child_insts, span);
+                }
+                "TypedefDecl" => {
+                    post_TypedefDecl(state, map);
+                }
+                /* Types are read from the `qualType` of whatever uses them */
+                kind if kind.ends_with("Type") => {}
                 // "ReturnStmt" => {}
-                "AnnotateAttr" => {}
-                "ImplicitCastExpr" => {}
+                /* Annotations were taken when they were entered; other attributes do not matter */
+                kind if kind.ends_with("Attr") || kind.ends_with("Comment") => {
+                    state.inst.truncate(inst_cnt);
+                }
+                "ImplicitCastExpr"
+                    if map.get("castKind").and_then(|v| v.as_str())
+                        == Some("ArrayToPointerDecay") =>
+                {
+                    post_array_decay(state, child_insts, span);
+                }
+                "ImplicitCastExpr" | "CStyleCastExpr" => {}
+                "ConstantExpr" => {}
                 "TranslationUnitDecl" => {}
-                "IntegerLiteral" => {}
+                "IntegerLiteral" | "CharacterLiteral" | "FloatingLiteral" | "StringLiteral" => {
+                    post_literal(state, map, span);
+                }
+                "UnaryExprOrTypeTraitExpr" => {
+                    post_UnaryExprOrTypeTraitExpr(state, inst_cnt, span);
+                }
+                "NullStmt" => {}
+                /* Enumerators are only ever read, as constants */
+                "EnumDecl" | "EnumConstantDecl" => {
+                    state.inst.truncate(inst_cnt);
+                }
                 _ => {
-                    println!("{}", kind_str);
-                    todo!()
+                    post_unsupported(state, kind_str, child_insts, span);
                 }
             }
         }
@@ -450,7 +941,7 @@ fn get_qual_type(value: &Value) -> Option<&str> {
     None
 }
 
-fn pre_process_referenced_decl(state: &mut ExecutionState, value: &Value) {
+fn pre_process_referenced_decl(state: &mut ExecutionState, value: &Value, span: &Span) {
     if let Value::Object(map) = value {
         let mut name: Option<&str> = None;
         let mut qual_type: Option<&str> = None;
@@ -474,77 +965,180 @@ fn pre_process_referenced_decl(state: &mut ExecutionState, value: &Value) {
         // let stmt = format!("{}", name.unwrap());
         // state.cmd.push(stmt);
 
+        /* An enumerator is a constant, not a variable */
+        if map.get("kind").and_then(|v| v.as_str()) == Some("EnumConstantDecl") {
+            let name = name.unwrap_or("").to_string();
+            state.inst.push(Inst::Eval(
+                span.clone(),
+                ExprDescriptor::Literal(span.clone(), name),
+            ));
+            return;
+        }
+
         // TODO:
         let variable = Variable {
-            mutable: false,
+            ty: qual_type::parse(qual_type.unwrap_or(""), &state.typedefs),
             ownership: false,
-            indirection: 0,
+            borrow: Borrow::None,
+            derived_from: None,
         };
 
-        let inst = Inst::VarDecl(0, name.unwrap().to_string(), variable);
+        let inst = Inst::VarDecl(span.clone(), name.unwrap().to_string(), variable);
         state.inst.push(inst);
     }
 }
 
-fn extract_annotation_from_source(line: u64, start: u64, end: u64) -> Option<String> {
-    // Open the file
-    let path = Path::new("dummy.c");
-    let file = File::open(&path).expect("Failed to open dummy.c");
+/*
+ * A location in the JSON dump. The dumper leaves out the file and line when
+ * they repeat the previous location, so they are carried over while walking.
+ */
+#[derive(Clone, Default)]
+struct DumpLoc {
+    file: String,
+    line: u64,
+    col: u64,
+    offset: u64,
+    tok_len: u64,
+}
 
-    // Use BufReader to read the file line by line
-    let reader = io::BufReader::new(file);
+/*
+ * (begin, end) pairs. `file_loc` is where the code shows up in the file the
+ * user is reading: the macro use site, or the argument for macro arguments.
+ */
+#[derive(Clone, Default)]
+struct DumpRange {
+    spelling: (DumpLoc, DumpLoc),
+    file_loc: (DumpLoc, DumpLoc),
+}
 
-    // Find the specified line (note: line numbers are 1-based)
-    let line_content = reader.lines().nth((line - 1) as usize);
+fn read_bare_loc(state: &mut ExecutionState, value: &Value) -> Option<DumpLoc> {
+    let offset = value.get("offset")?.as_u64()?;
+    if let Some(file) = value.get("file").and_then(|v| v.as_str()) {
+        state.last_file = file.to_string();
+    }
+    if let Some(line) = value.get("line").and_then(|v| v.as_u64()) {
+        state.last_line = line;
+    }
 
-    match line_content {
-        Some(Ok(line_text)) => {
-            // Extract the part of the line between start and end positions
-            let line_len = line_text.len() as u64;
-            if start < line_len && end <= line_len && start <= end {
-                let rv = line_text[start as usize..end as usize].to_string();
+    Some(DumpLoc {
+        file: state.last_file.clone(),
+        line: state.last_line,
+        col: value
+            .get("col")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default(),
+        offset,
+        tok_len: value
+            .get("tokLen")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default(),
+    })
+}
 
-                let rv = rv.as_str();
+/* Returns (spelling, file_loc) */
+fn read_loc(state: &mut ExecutionState, value: &Value) -> Option<(DumpLoc, DumpLoc)> {
+    if let Some(spelling) = value.get("spellingLoc") {
+        let spelling = read_bare_loc(state, spelling)?;
+        let expansion_loc = value.get("expansionLoc")?;
+        let expansion = read_bare_loc(state, expansion_loc)?;
+        let is_macro_arg = expansion_loc
+            .get("isMacroArgExpansion")
+            .and_then(|v| v.as_bool())
+            .unwrap_or_default();
+        if is_macro_arg {
+            return Some((spelling.clone(), spelling));
+        }
+        return Some((spelling, expansion));
+    }
 
-                let start = rv.find('\"')? + 1; // First double quote after `annotate(`
-                let end = rv.rfind('\"')?; // Last double quote
+    let loc = read_bare_loc(state, value)?;
+    Some((loc.clone(), loc))
+}
 
-                // Extract the substring between the quotes
-                return Some(rv[start..end].to_string());
-            } else {
-                return None;
-            }
-        }
-        _ => return None,
-        None => return None,
+/* Must see every location in document order to keep the elided fields right */
+fn read_node_range(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+) -> Option<DumpRange> {
+    if let Some(loc) = map.get("loc") {
+        let _ = read_loc(state, loc);
     }
+
+    let range = map.get("range")?;
+    let begin = read_loc(state, range.get("begin")?);
+    let end = read_loc(state, range.get("end")?);
+    let (begin, end) = (begin?, end?);
+
+    Some(DumpRange {
+        spelling: (begin.0, end.0),
+        file_loc: (begin.1, end.1),
+    })
 }
 
-fn parse_annotation(state: &mut ExecutionState, value: &Value) -> Option<String> {
-    if let Value::Object(map) = value {
-        let l0 = map.get("begin")?.get("spellingLoc")?.get("line")?;
+/* Implicit nodes have no range and get an empty span */
+fn to_span(range: Option<&DumpRange>) -> Span {
+    let Some(range) = range else {
+        return Span::default();
+    };
 
-        let c0 = map.get("begin")?.get("spellingLoc")?.get("col")?;
+    let (begin, end) = &range.file_loc;
+    Span {
+        begin: Location {
+            file: begin.file.clone(),
+            line: begin.line,
+            col: begin.col,
+        },
+        end: Location {
+            file: end.file.clone(),
+            line: end.line,
+            col: end.col,
+        },
+    }
+}
 
-        let l1 = map.get("end")?.get("spellingLoc")?.get("line")?;
+fn read_source<'a>(state: &'a mut ExecutionState, file: &str) -> Option<&'a String> {
+    if !state.sources.contains_key(file) {
+        /* Relative names are relative to where clang was run */
+        let mut path = PathBuf::from(file);
+        if path.is_relative() {
+            path = state.source.parent().unwrap_or(Path::new("")).join(path);
+        }
+        let text = fs::read_to_string(path).ok()?;
+        state.sources.insert(file.to_string(), text);
+    }
 
-        let c1 = map.get("end")?.get("spellingLoc")?.get("col")?;
+    state.sources.get(file)
+}
 
-        assert!(l0.as_u64() == l1.as_u64());
-        let annotation =
-            extract_annotation_from_source(l0.as_u64()?, c0.as_u64()? - 1, c1.as_u64()?);
-        return annotation;
+/*
+ * The JSON dump does not carry annotate() arguments, so take the first string
+ * literal inside the attribute's own spelling range. That range points at the
+ * macro definition or header the attribute was written in.
+ */
+fn annotation_from_source(state: &mut ExecutionState, range: &DumpRange) -> Option<String> {
+    let (begin, end) = &range.spelling;
+    if begin.file != end.file {
+        return None;
     }
 
-    None
+    let text = read_source(state, &begin.file)?;
+    let attr = text.get(begin.offset as usize..(end.offset + end.tok_len) as usize)?;
+
+    let start = attr.find('"')? + 1;
+    let len = attr[start..].find('"')?;
+    Some(attr[start..start + len].to_string())
 }
 
-fn pre_processing(state: &mut ExecutionState, map: &serde_json::Map<std::string::String, Value>) {
+fn pre_processing(
+    state: &mut ExecutionState,
+    map: &serde_json::Map<std::string::String, Value>,
+    node_range: Option<&DumpRange>,
+    span: &Span,
+) {
     let mut kind: Option<&str> = None;
     let mut name: Option<&str> = None;
     let mut qual_type: Option<&str> = None;
     let mut inner: Option<&Value> = None;
-    let mut range: Option<&Value> = None;
     let mut referenced_decl: Option<&Value> = None;
 
     // Traverse nested objects or arrays
@@ -552,9 +1146,7 @@ fn pre_processing(state: &mut ExecutionState, map: &serde_json::Map<std::string:
         match l.as_str() {
             "id" => { /* don't care */ }
             "loc" => { /* don't care */ }
-            "range" => {
-                range = Some(v);
-            }
+            "range" => { /* see read_node_range */ }
             "isUsed" => { /* don't care */ }
             "kind" => kind = v.as_str(),
             "name" => name = v.as_str(),
@@ -579,25 +1171,36 @@ fn pre_processing(state: &mut ExecutionState, map: &serde_json::Map<std::string:
             // let push = format!("{} {}", qual_type.unwrap_or(""), name.unwrap_or(""));
             // state.cmd.push(push);
 
-            /* TODO */
+            /* Annotations and the initializer are children; see post_VarDecl */
             let var = Variable {
-                mutable: false,
+                ty: qual_type::parse(qual_type.unwrap_or(""), &state.typedefs),
                 ownership: false,
-                indirection: 0,
+                borrow: Borrow::None,
+                derived_from: None,
             };
 
-            state
-                .inst
-                .push(Inst::VarDecl(0, name.unwrap_or("").to_string(), var));
+            state.inst.push(Inst::VarDecl(
+                span.clone(),
+                name.unwrap_or("").to_string(),
+                var,
+            ));
         }
         "DeclStmt" => {}
+        "RecordDecl" => state.pending_record_annotations.push(Vec::new()),
+        /* The dump lists a struct's own attributes before its fields */
+        "FieldDecl" => {
+            let annotations = take_ownership_annotations(state);
+            if let Some(pending) = state.pending_record_annotations.last_mut() {
+                pending.extend(annotations);
+            }
+        }
         "TypedefDecl" => {
             return; /* Don't care */
         }
         "CallExpr" => {}
         "DeclRefExpr" => {
             if referenced_decl != None {
-                pre_process_referenced_decl(state, referenced_decl.unwrap());
+                pre_process_referenced_decl(state, referenced_decl.unwrap(), span);
             }
         }
         "ParmVarDecl" => {
@@ -613,70 +1216,233 @@ fn pre_processing(state: &mut ExecutionState, map: &serde_json::Map<std::string:
     }
 
     if kind.unwrap_or("") == "AnnotateAttr" {
-        if range != None {
-            let annotation = parse_annotation(state, range.unwrap());
-            if annotation != None {
-                state.annotation = annotation.unwrap();
-            }
+        let annotation = match map.get("annotation").and_then(|v| v.as_str()) {
+            /* libclang frontend hands us the text directly */
+            Some(annotation) => Some(annotation.to_string()),
+            None => node_range.and_then(|range| annotation_from_source(state, range)),
+        };
+        match annotation {
+            Some(annotation) => state.annotations.push(annotation),
+            None => state.diagnostics.push(Diagnostic::warning(
+                Code::UnreadableAnnotation,
+                "unable to read the annotation text; it is ignored".to_string(),
+                span,
+            )),
         }
     }
 
-    let indent = "  ".repeat(state.depth as usize);
-    println!("{}{}: {}", indent, kind.unwrap_or(""), name.unwrap_or(""));
+    if state.dump_ast {
+        let indent = "  ".repeat(state.depth as usize);
+        println!("{}{}: {}", indent, kind.unwrap_or(""), name.unwrap_or(""));
+    }
 }
 
-fn traverse_json(state: &mut ExecutionState, value: &Value) -> u32 {
+/* Returns how many insts each child left behind */
+fn traverse_json(state: &mut ExecutionState, value: &Value) -> Vec<usize> {
     state.depth += 1;
-    let mut children = 0;
+    let mut child_insts = Vec::new();
     if let Value::Object(map) = value {
-        pre_processing(state, map);
+        let node_range = read_node_range(state, map);
+        let span = to_span(node_range.as_ref());
+
+        pre_processing(state, map, node_range.as_ref(), &span);
 
         let inst_count = state.inst.len();
 
         if let Some(inner) = map.get("inner") {
-            children = traverse_json(state, inner);
+            child_insts = traverse_json(state, inner);
         }
 
-        post_processing(map, state, children, inst_count);
+        post_processing(map, state, &child_insts, inst_count, &span);
     } else if let Value::Array(arr) = value {
         for val in arr {
-            children += 1;
+            let inst_count = state.inst.len();
             traverse_json(state, val);
+            child_insts.push(state.inst.len().saturating_sub(inst_count));
         }
     }
     state.depth -= 1;
 
-    children
+    child_insts
 }
 
-fn main() {
-    // Read the contents of the JSON file
-    let file_path = "dummy.json";
-    let json_content = fs::read_to_string(file_path).expect("Failed to read file");
+const USAGE: &str = "usage: c_borrow_checker [--dump-ast] [-p <build-dir>] [-I <dir>] [-D <macro>] [-std=<std>] [<file>...]";
+
+struct Options {
+    dump_ast: bool,
+    clang_args: Vec<String>,
+    compile_commands: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
 
-    // Parse the JSON content into a serde_json::Value
-    let parsed_json: Value = serde_json::from_str(&json_content).expect("Failed to parse JSON");
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options {
+        dump_ast: false,
+        clang_args: Vec::new(),
+        compile_commands: None,
+        inputs: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--dump-ast" => opts.dump_ast = true,
+            "-p" => {
+                let path = args.next().ok_or("-p expects a path")?;
+                opts.compile_commands = Some(PathBuf::from(path));
+            }
+            "-I" | "-D" | "-U" | "-include" => {
+                /* Flag with a separate value */
+                let value = args.next().ok_or(format!("{} expects a value", arg))?;
+                opts.clang_args.push(arg);
+                opts.clang_args.push(value);
+            }
+            _ if arg.starts_with("-I")
+                || arg.starts_with("-D")
+                || arg.starts_with("-U")
+                || arg.starts_with("-std=") =>
+            {
+                opts.clang_args.push(arg);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option '{}'\n{}", arg, USAGE));
+            }
+            _ => opts.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if opts.inputs.is_empty() && opts.compile_commands.is_none() {
+        return Err(USAGE.to_string());
+    }
+
+    Ok(opts)
+}
+
+/*
+ * A `.json` input is an AST already dumped with `clang -Xclang -ast-dump=json`
+ * and is paired with the `.c` file next to it. Anything else is treated as C
+ * source and parsed in-process through libclang with the user's flags.
+ */
+fn load_ast(input: &Path, clang_args: &[String]) -> Result<(Value, PathBuf), String> {
+    if input.extension().is_some_and(|ext| ext == "json") {
+        let json_content = fs::read_to_string(input).map_err(|e| e.to_string())?;
+        let parsed_json = serde_json::from_str(&json_content).map_err(|e| e.to_string())?;
+        return Ok((parsed_json, input.with_extension("c")));
+    }
+
+    let parsed_json = libclang::parse(input, clang_args)?;
+
+    Ok((parsed_json, input.to_path_buf()))
+}
+
+fn check_file(
+    input: &Path,
+    clang_args: &[String],
+    dump_ast: bool,
+) -> Result<Vec<Diagnostic>, String> {
+    let (parsed_json, source) = load_ast(input, clang_args)?;
 
     let mut state = ExecutionState {
         // params: None,
         depth: 0,
+        dump_ast,
         // cmd: Vec::new(),
-        annotation: String::new(),
+        annotations: Vec::new(),
+        return_borrow: None,
+        typedefs: HashMap::new(),
+        typedef_annotations: HashMap::new(),
+        record_annotations: HashMap::new(),
+        pending_record_annotations: Vec::new(),
+        source,
+        sources: HashMap::new(),
+        last_file: String::new(),
+        last_line: 0,
         // declared_functions: HashMap::new(),
         // variables: HashMap::new(),
-        var_decl: 0,
         inst: Vec::new(),
         tl: TranslationUnit {
             sub_unit: Vec::new(),
         },
+        diagnostics: Vec::new(),
     };
 
     // Start recursive traversal
     traverse_json(&mut state, &parsed_json);
 
-    verify(&state.tl);
+    let mut diagnostics = std::mem::take(&mut state.diagnostics);
+    diagnostics.extend(verify(&state.tl));
+    let file = input.display().to_string();
+    Ok(diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.in_file(&file))
+        .collect())
+}
+
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            exit(2);
+        }
+    };
+
+    /* Each translation unit is checked with its own flags */
+    let mut jobs: Vec<(PathBuf, Vec<String>)> = opts
+        .inputs
+        .iter()
+        .map(|input| (input.clone(), opts.clang_args.clone()))
+        .collect();
+
+    if let Some(path) = &opts.compile_commands {
+        match compile_commands::load(path) {
+            Ok(commands) => {
+                for mut command in commands {
+                    command.args.extend(opts.clang_args.iter().cloned());
+                    jobs.push((command.file, command.args));
+                }
+            }
+            Err(msg) => {
+                eprintln!("error: {}", msg);
+                exit(2);
+            }
+        }
+    }
+
+    let mut failed = 0;
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (input, clang_args) in jobs.iter() {
+        match check_file(input, clang_args, opts.dump_ast) {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics.iter() {
+                    match diagnostic.severity {
+                        Severity::Error => errors += 1,
+                        Severity::Warning => warnings += 1,
+                        Severity::Note => {}
+                    }
+                    eprintln!("{}", diagnostic);
+                }
+            }
+            Err(msg) => {
+                eprintln!("{}: error: {}", input.display(), msg.trim_end());
+                failed += 1;
+            }
+        }
+    }
 
-    println!("Completed!");
+    println!(
+        "Completed! {} translation unit(s) checked, {} failed, {} error(s), {} warning(s).",
+        jobs.len(),
+        failed,
+        errors,
+        warnings
+    );
+
+    /* Non-zero exit lets CI gate on the result */
+    if failed != 0 || errors != 0 {
+        exit(1);
+    }
 }
 
 /*
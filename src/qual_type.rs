@@ -0,0 +1,339 @@
+use crate::def::*;
+
+use std::collections::HashMap;
+
+/*
+ * Parse a type the way clang spells it in `qualType`, such as `int *const *`
+ * or `void (*)(int *)`. Typedef names are looked up in `typedefs`; ones we
+ * have not seen are kept as opaque scalars.
+ */
+pub fn parse(qual_type: &str, typedefs: &HashMap<String, Type>) -> Type {
+    let tokens = tokenize(qual_type);
+    let mut parser = Parser {
+        tokens: &tokens,
+        at: 0,
+        typedefs,
+    };
+    parser.type_name()
+}
+
+fn is_word(token: &str) -> bool {
+    token.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut in_word = false;
+    for c in text.chars() {
+        let word_char = c.is_alphanumeric() || c == '_';
+        if word_char && in_word {
+            tokens.last_mut().unwrap().push(c);
+        } else if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+        in_word = word_char;
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    at: usize,
+    typedefs: &'a HashMap<String, Type>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.at).map(String::as_str)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    /* Move past the `)` matching a `(` already eaten */
+    fn skip_parens(&mut self) {
+        let mut depth = 1;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                "(" => depth += 1,
+                ")" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn nested(&self, tokens: &'a [String]) -> Parser<'a> {
+        Parser {
+            tokens,
+            at: 0,
+            typedefs: self.typedefs,
+        }
+    }
+
+    fn type_name(&mut self) -> Type {
+        let base = self.specifiers();
+        self.declarator(base)
+    }
+
+    /* `const unsigned int`, `struct Set`, `buf_t` and the like */
+    fn specifiers(&mut self) -> Type {
+        let mut is_const = false;
+        let mut words = Vec::new();
+        let mut kind = None;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                "const" => is_const = true,
+                "volatile" | "restrict" | "__restrict" => {}
+                "__attribute__" => {
+                    if self.eat("(") {
+                        self.skip_parens();
+                    }
+                }
+                /* Anonymous ones are spelled `struct (unnamed at t.c:1:1)` */
+                "struct" | "union" | "enum" => {
+                    let tag = match self.peek() {
+                        Some("(") => {
+                            self.at += 1;
+                            self.skip_parens();
+                            String::new()
+                        }
+                        Some(tag) => {
+                            self.at += 1;
+                            tag.to_string()
+                        }
+                        None => String::new(),
+                    };
+                    kind = Some(match token {
+                        "enum" => TypeKind::Scalar(format!("enum {}", tag)),
+                        _ => TypeKind::Record(tag),
+                    });
+                }
+                _ if is_word(token) => words.push(token),
+                _ => {
+                    self.at -= 1;
+                    break;
+                }
+            }
+        }
+
+        let mut ty = match (kind, words.as_slice()) {
+            (Some(kind), _) => Type::new(kind),
+            (None, ["void"]) => Type::new(TypeKind::Void),
+            (None, [name]) if self.typedefs.contains_key(*name) => Type {
+                typedef: Some(name.to_string()),
+                ..self.typedefs[*name].clone()
+            },
+            (None, words) => Type::new(TypeKind::Scalar(words.join(" "))),
+        };
+        ty.is_const |= is_const;
+        ty
+    }
+
+    /* Only qualifiers can follow a `*`, and they apply to the pointer itself */
+    fn qualifiers(&mut self) -> bool {
+        let mut is_const = false;
+        while let Some(token) = self.peek().filter(|token| is_word(token)) {
+            is_const |= token == "const";
+            self.at += 1;
+        }
+        is_const
+    }
+
+    fn declarator(&mut self, base: Type) -> Type {
+        if self.eat("*") {
+            let mut pointer = Type::new(TypeKind::Pointer(Box::new(base)));
+            pointer.is_const = self.qualifiers();
+            return self.declarator(pointer);
+        }
+
+        /* In `(*)[4]` the array comes first and the pointer points to it */
+        let grouped = matches!(
+            self.tokens.get(self.at + 1).map(String::as_str),
+            Some("*" | "(")
+        );
+        if grouped && self.eat("(") {
+            let inner = self.at;
+            self.skip_parens();
+            let outer = self.suffixes(base);
+            return self
+                .nested(&self.tokens[inner..self.at.saturating_sub(1).max(inner)])
+                .declarator(outer);
+        }
+
+        self.suffixes(base)
+    }
+
+    /* `[4]` and `(int)` bind tighter than `*`; the leftmost is the outermost */
+    fn suffixes(&mut self, base: Type) -> Type {
+        if self.eat("[") {
+            while let Some(token) = self.peek() {
+                self.at += 1;
+                if token == "]" {
+                    break;
+                }
+            }
+            let element = self.suffixes(base);
+            return Type::new(TypeKind::Array(Box::new(element)));
+        }
+        if self.eat("(") {
+            let params = self.parameters();
+            let ret = self.suffixes(base);
+            return Type::new(TypeKind::Function(Box::new(ret), params));
+        }
+        base
+    }
+
+    /* Up to and including the closing `)`; `(void)` and `...` add nothing */
+    fn parameters(&mut self) -> Vec<Type> {
+        let mut params = Vec::new();
+        let mut start = self.at;
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            self.at += 1;
+            match token {
+                "(" | "[" => depth += 1,
+                ")" | "]" if depth > 0 => depth -= 1,
+                "," | ")" => {
+                    let tokens = &self.tokens[start..self.at - 1];
+                    let is_param = tokens.iter().any(|token| token != ".") && tokens != ["void"];
+                    if is_param {
+                        params.push(self.nested(tokens).type_name());
+                    }
+                    if token == ")" {
+                        break;
+                    }
+                    start = self.at;
+                }
+                _ => {}
+            }
+        }
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ty(kind: TypeKind, is_const: bool) -> Type {
+        Type {
+            is_const,
+            ..Type::new(kind)
+        }
+    }
+
+    fn scalar(name: &str) -> Type {
+        Type::new(TypeKind::Scalar(name.to_string()))
+    }
+
+    fn pointer(to: Type) -> Type {
+        Type::new(TypeKind::Pointer(Box::new(to)))
+    }
+
+    fn parse_plain(qual_type: &str) -> Type {
+        parse(qual_type, &HashMap::new())
+    }
+
+    #[test]
+    fn const_pointer() {
+        assert_eq!(
+            parse_plain("int *const"),
+            ty(TypeKind::Pointer(Box::new(scalar("int"))), true)
+        );
+    }
+
+    #[test]
+    fn pointer_to_pointer_to_const() {
+        let int = ty(TypeKind::Scalar("int".to_string()), true);
+        assert_eq!(parse_plain("const int **"), pointer(pointer(int)));
+    }
+
+    #[test]
+    fn function_pointer() {
+        let function = TypeKind::Function(
+            Box::new(Type::new(TypeKind::Void)),
+            vec![pointer(scalar("int"))],
+        );
+        assert_eq!(parse_plain("void (*)(int *)"), pointer(Type::new(function)));
+    }
+
+    #[test]
+    fn function_parameters() {
+        let function = TypeKind::Function(
+            Box::new(pointer(scalar("char"))),
+            vec![scalar("int"), pointer(pointer(scalar("char")))],
+        );
+        assert_eq!(
+            parse_plain("char *(int, char **, ...)"),
+            Type::new(function)
+        );
+        assert_eq!(
+            parse_plain("int (void)"),
+            Type::new(TypeKind::Function(Box::new(scalar("int")), vec![]))
+        );
+    }
+
+    #[test]
+    fn pointer_to_array() {
+        let array = Type::new(TypeKind::Array(Box::new(scalar("int"))));
+        assert_eq!(parse_plain("int (*)[4]"), pointer(array));
+
+        let pointers = TypeKind::Array(Box::new(pointer(scalar("int"))));
+        assert_eq!(parse_plain("int *[4]"), Type::new(pointers));
+    }
+
+    #[test]
+    fn anonymous_struct() {
+        assert_eq!(
+            parse_plain("struct (unnamed at t.c:1:1) *"),
+            pointer(Type::new(TypeKind::Record(String::new())))
+        );
+        assert_eq!(
+            parse_plain("const struct Set"),
+            ty(TypeKind::Record("Set".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn restrict() {
+        assert_eq!(parse_plain("char *restrict"), pointer(scalar("char")));
+        assert_eq!(
+            parse_plain("const char *__restrict"),
+            pointer(ty(TypeKind::Scalar("char".to_string()), true))
+        );
+    }
+
+    #[test]
+    fn typedef_resolution() {
+        let mut typedefs = HashMap::new();
+        let buf = pointer(Type::new(TypeKind::Record("buf".to_string())));
+        typedefs.insert("buf_t".to_string(), buf.clone());
+
+        let resolved = Type {
+            typedef: Some("buf_t".to_string()),
+            ..buf.clone()
+        };
+        assert_eq!(parse("buf_t", &typedefs), resolved);
+        assert_eq!(
+            parse("const buf_t", &typedefs),
+            Type {
+                is_const: true,
+                ..resolved.clone()
+            }
+        );
+        assert_eq!(parse("buf_t *", &typedefs), pointer(resolved));
+        assert_eq!(parse("size_t", &typedefs), scalar("size_t"));
+    }
+}
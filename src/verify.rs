@@ -1,131 +1,1246 @@
-
+use crate::cfg::*;
 use crate::def::*;
+use crate::diagnostic::*;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 // use crate::TranslationUnitSet::Function;
 
+#[derive(Clone, PartialEq)]
+enum Status {
+    Live,
+    MaybeOwned, /* owned on some paths only, holding nothing on the others */
+    Moved(Span),
+    MaybeMoved(Span), /* moved on some paths only */
+    Dropped(Span),
+    MaybeDropped(Span), /* dropped on some paths only */
+}
+
+impl Status {
+    /* The same, but only on some paths */
+    fn maybe(&self) -> Status {
+        match self {
+            Status::Live => Status::Live,
+            Status::MaybeOwned => Status::MaybeOwned,
+            Status::Moved(span) | Status::MaybeMoved(span) => Status::MaybeMoved(span.clone()),
+            Status::Dropped(span) | Status::MaybeDropped(span) => {
+                Status::MaybeDropped(span.clone())
+            }
+        }
+    }
+
+    /* Still holds its value, if it has one */
+    fn is_live(&self) -> bool {
+        matches!(self, Status::Live | Status::MaybeOwned)
+    }
+}
+
+/* A borrow of `owner`, live as long as whatever holds it */
+#[derive(Clone, PartialEq)]
+struct Loan {
+    owner: Place,
+    exclusive: bool, /* BORROW_MUT */
+    address: bool,   /* taken with `&`, so it points into the owner's own storage */
+    span: Span,
+}
+
+#[derive(Clone, PartialEq)]
+struct Binding {
+    variable: Variable,
+    status: Status,
+    acquired: Span,     /* where the variable got its value */
+    loan: Option<Loan>, /* set when the variable is an annotated borrow */
+    must_own: bool,     /* declared MOVE, so only owned values may be stored */
+}
+
+impl Binding {
+    fn new(variable: Variable, acquired: &Span) -> Binding {
+        Binding {
+            must_own: variable.ownership,
+            variable,
+            status: Status::Live,
+            acquired: acquired.clone(),
+            loan: None,
+        }
+    }
+
+    fn join(&mut self, other: &Binding) {
+        if self.loan.is_none() {
+            self.loan = other.loan.clone();
+        }
+        let owned_on_one_side = self.variable.ownership != other.variable.ownership;
+        if other.variable.ownership && !self.variable.ownership {
+            self.acquired = other.acquired.clone();
+        }
+        self.variable.ownership |= other.variable.ownership;
+        self.status = match (&self.status, &other.status) {
+            (Status::Live, Status::Live) if !owned_on_one_side => Status::Live,
+            (Status::Live | Status::MaybeOwned, Status::Live | Status::MaybeOwned) => {
+                Status::MaybeOwned
+            }
+            /* Gone on every path; keep the first site */
+            (Status::Moved(_) | Status::Dropped(_), Status::Moved(_) | Status::Dropped(_)) => {
+                self.status.clone()
+            }
+            (Status::Live | Status::MaybeOwned, gone) | (gone, _) => gone.maybe(),
+        };
+    }
+}
+
+/*
+ * Ownership state at one program point. Where control flow meets, the states
+ * of all incoming edges are merged.
+ */
+#[derive(Clone, Default, PartialEq)]
 struct State {
-    variables: HashMap<String, Variable>,
+    variables: BTreeMap<Place, Binding>,
+}
+
+impl State {
+    /* A part without a binding is still as it was when the whole got its value */
+    fn has_base(&self, place: &Place) -> bool {
+        place
+            .parent()
+            .is_some_and(|parent| self.variables.contains_key(&parent))
+    }
+
+    fn join(&mut self, other: &State) {
+        for (label, binding) in other.variables.iter() {
+            let has_base = self.has_base(label);
+            match self.variables.get_mut(label) {
+                Some(current) => current.join(binding),
+                None if has_base => {
+                    let mut binding = binding.clone();
+                    binding.status = binding.status.maybe();
+                    self.variables.insert(label.clone(), binding);
+                }
+                None => {
+                    self.variables.insert(label.clone(), binding.clone());
+                }
+            }
+        }
+        for (label, binding) in self.variables.iter_mut() {
+            if !other.variables.contains_key(label) && other.has_base(label) {
+                binding.status = binding.status.maybe();
+            }
+        }
+    }
+}
+
+/* A place that gets a new value gets new parts too */
+fn forget_parts(state: &mut State, place: &Place) {
+    state.variables.retain(|part, _| !part.is_within(place));
+}
+
+/* An unannotated parameter or return value takes the annotation it had before */
+fn inherit_annotation(variable: &mut Variable, earlier: &Variable) {
+    if !variable.ownership && variable.borrow == Borrow::None && variable.derived_from.is_none() {
+        variable.ownership = earlier.ownership;
+        variable.borrow = earlier.borrow;
+        variable.derived_from = earlier.derived_from;
+    }
+}
+
+/*
+ * Annotations usually sit on the prototype in a header while the definition
+ * is left plain. Function attributes are carried over by clang, but those on
+ * parameters are not.
+ */
+fn merge_declaration(earlier: &Function, function: &Function) -> Function {
+    let mut function = function.clone();
+    function.drop |= earlier.drop;
+    if let (Some(ret_val), Some(earlier)) = (function.ret_val.as_mut(), earlier.ret_val.as_ref()) {
+        inherit_annotation(ret_val, earlier);
+    }
+    for ((_, param), (_, earlier)) in function.param.iter_mut().zip(earlier.param.iter()) {
+        inherit_annotation(param, earlier);
+    }
+
+    /* The body starts from the parameter declarations */
+    let mut params = function.param.iter();
+    for inst in function.inst.iter_mut() {
+        if let Inst::ParamDecl(_, _, variable) = inst {
+            if let Some((_, param)) = params.next() {
+                *variable = param.clone();
+            }
+        }
+    }
+
+    function
+}
+
+/* Shared by all paths: what is known about the translation unit and the findings */
+struct Context {
     functions: HashMap<String, Function>,
-    is_forked: bool,
+    records: HashMap<String, Record>,
+    current: String, /* the function being checked */
+    diagnostics: Vec<Diagnostic>,
+    report: bool, /* off while the states are still changing */
 }
 
-fn eval(state: &State, expr: &ExprDescriptor) -> Option<Variable> {
+impl Context {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        if self.report {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+fn eval(ctx: &mut Context, state: &mut State, expr: &ExprDescriptor) -> Option<Variable> {
     match expr {
-        ExprDescriptor::FunctionCall(name, args) => {
-            println!("### eval: Function");
-            let mut vars = Vec::new();
-            for arg in args.iter() {
-                vars.push(eval(state, arg));
+        ExprDescriptor::FunctionCall(span, name, args) => {
+            let function = ctx.functions.get(name).cloned();
+            if function.is_none() {
+                ctx.report(Diagnostic::warning(
+                    Code::UnknownFunction,
+                    format!("call to unknown function `{}`", name),
+                    span,
+                ));
+            }
+
+            /* Borrows passed to the callee last for the whole call */
+            let mut loans = Vec::new();
+
+            /* Arguments are evaluated in order, so `f(a, a)` sees the first move */
+            for (k, arg) in args.iter().enumerate() {
+                if let ExprDescriptor::Place(arg_span, place) = arg {
+                    if function.as_ref().is_some_and(|f| f.drop) {
+                        drop_value(ctx, state, place, arg_span);
+                        continue;
+                    }
+                }
+
+                let value = eval(ctx, state, arg);
+
+                /* Variadic arguments have no parameter to check against */
+                let param = function.as_ref().and_then(|f| f.param.get(k));
+                if let Some((param_name, param)) = param {
+                    check_argument(ctx, state, name, param_name, param, arg, value.as_ref());
+                }
+
+                /* Pointers not handed over are borrowed, shared unless BORROW_MUT */
+                if let (Some((_, param)), Some(value)) = (param, value) {
+                    if !param.ownership && value.ty.pointee().is_some() {
+                        let exclusive = param.borrow == Borrow::Mutable;
+                        let loan = match arg {
+                            ExprDescriptor::Place(arg_span, place) => {
+                                Some((place, pointer_loan(state, place, exclusive, arg_span)))
+                            }
+                            ExprDescriptor::AddressOf(arg_span, place) => {
+                                Some((place, address_loan(state, place, exclusive, arg_span)))
+                            }
+                            _ => None,
+                        };
+                        if let Some((holder, loan)) = loan {
+                            check_loan(ctx, state, holder, &loan, &loans);
+                            loans.push(loan);
+                        }
+                    }
+                }
+            }
+
+            function?.ret_val
+        }
+        ExprDescriptor::Literal(_, _) => None,
+        ExprDescriptor::Temporary(_, insts) => {
+            process_operands(ctx, state, insts);
+            None
+        }
+        /* A pointer to the variable, owned by nobody */
+        ExprDescriptor::AddressOf(_, place) => {
+            bind_place(ctx, state, place);
+            let variable = &state.variables.get(place)?.variable;
+            Some(Variable {
+                ty: Type::new(TypeKind::Pointer(Box::new(variable.ty.clone()))),
+                ownership: false,
+                borrow: Borrow::None,
+                derived_from: None,
+            })
+        }
+        ExprDescriptor::Place(span, place) => {
+            bind_place(ctx, state, place);
+            if !check_base(ctx, state, place, span) {
+                return None;
+            }
+
+            match state.variables.get(place) {
+                Some(binding) => {
+                    check_use(ctx, place, &binding.status, span);
+                    if let Some(loan) = &binding.loan {
+                        check_source(ctx, state, place, loan, span);
+                    }
+                    Some(binding.variable.clone())
+                }
+                /* Fields of structs that were never defined are not tracked */
+                None => {
+                    if !state.variables.contains_key(&Place::variable(&place.base)) {
+                        ctx.report(Diagnostic::warning(
+                            Code::UnknownVariable,
+                            format!("use of unknown variable `{}`", place.base),
+                            span,
+                        ));
+                    }
+                    None
+                }
             }
+        }
+    }
+}
+
+/* A pointer read from `place` borrows what `place` borrows, or else what it points to */
+fn pointer_loan(state: &State, place: &Place, exclusive: bool, span: &Span) -> Loan {
+    match state.variables.get(place).and_then(|b| b.loan.as_ref()) {
+        Some(loan) => Loan {
+            owner: loan.owner.clone(),
+            exclusive,
+            address: loan.address,
+            span: span.clone(),
+        },
+        None => Loan {
+            owner: place.clone(),
+            exclusive,
+            address: false,
+            span: span.clone(),
+        },
+    }
+}
+
+/* `*p`, and `p[i]` unless `p` is an array held in the variable itself */
+fn through_pointer(state: &State, base: &Place, step: &Projection) -> bool {
+    match step {
+        Projection::Deref => true,
+        Projection::Index => !state
+            .variables
+            .get(base)
+            .is_some_and(|binding| matches!(binding.variable.ty.kind, TypeKind::Array(_))),
+        Projection::Field(_) => false,
+    }
+}
 
-            /* TODO: borrow-checker to verify variables against function parameter list */
+/* Stored in the variable itself rather than behind a pointer */
+fn is_direct(state: &State, place: &Place) -> bool {
+    let mut base = Place::variable(&place.base);
+    for step in place.projection.iter() {
+        if through_pointer(state, &base, step) {
+            return false;
+        }
+        base = base.project(step.clone());
+    }
+    true
+}
 
-            println!("### eval: name = {}", name);
-            state.functions.get(name)?.ret_val
+/* `&x` borrows `x` itself; `&p->a` borrows again whatever `p` borrows */
+fn address_loan(state: &State, place: &Place, exclusive: bool, span: &Span) -> Loan {
+    let mut pointer = place.clone();
+    while let Some(step) = pointer.projection.pop() {
+        if !through_pointer(state, &pointer, &step) {
+            continue;
         }
-        ExprDescriptor::LocalVariable(name) => {
-            println!("### eval: Variable name = {}", name);
-            Some(state.variables.get(name).unwrap().clone())
+        if let Some(loan) = state.variables.get(&pointer).and_then(|b| b.loan.as_ref()) {
+            return Loan {
+                owner: loan.owner.clone(),
+                exclusive,
+                address: loan.address,
+                span: span.clone(),
+            };
         }
     }
+
+    Loan {
+        owner: place.clone(),
+        exclusive,
+        address: is_direct(state, place),
+        span: span.clone(),
+    }
 }
 
-enum ExitCode {
-    EarlyExit,
+/*
+ * Rust-style exclusivity: an owner may have one mutable borrow or any number
+ * of shared ones. `holder` is the variable the new borrow is taken through,
+ * whose own loan does not conflict with it.
+ */
+fn check_loan(ctx: &mut Context, state: &State, holder: &Place, loan: &Loan, call_loans: &[Loan]) {
+    let held = state
+        .variables
+        .iter()
+        .filter(|(place, _)| *place != holder)
+        .filter_map(|(_, binding)| binding.loan.as_ref());
+    let Some(other) = held
+        .chain(call_loans.iter())
+        .find(|other| other.owner.overlaps(&loan.owner) && (loan.exclusive || other.exclusive))
+    else {
+        return;
+    };
+
+    let (message, label) = match (loan.exclusive, other.exclusive) {
+        (true, true) => (
+            format!(
+                "cannot borrow `{}` as mutable more than once at a time",
+                loan.owner
+            ),
+            "first mutable borrow here",
+        ),
+        (true, false) => (
+            format!(
+                "cannot borrow `{}` as mutable because it is also borrowed as shared",
+                loan.owner
+            ),
+            "shared borrow here",
+        ),
+        (false, _) => (
+            format!(
+                "cannot borrow `{}` as shared because it is also borrowed as mutable",
+                loan.owner
+            ),
+            "mutable borrow here",
+        ),
+    };
+    ctx.report(
+        Diagnostic::error(Code::BorrowConflict, message, &loan.span).with_label(&other.span, label),
+    );
 }
 
-use std::{
-    process::{exit, Command},
-    thread::sleep,
-    time::Duration,
-};
+/* A borrow may only be used while what it borrows from is still there */
+fn check_source(ctx: &mut Context, state: &State, name: &Place, loan: &Loan, span: &Span) {
+    let Some(owner) = state.variables.get(&loan.owner) else {
+        return;
+    };
 
-use nix::{
-    sys::wait::waitpid,
-    unistd::{fork, write, ForkResult},
-};
+    let (what, site, label) = match &owner.status {
+        Status::Live | Status::MaybeOwned => return,
+        Status::Moved(site) => ("was moved", site, "value moved here"),
+        Status::MaybeMoved(site) => (
+            "may have been moved",
+            site,
+            "value moved here on some paths",
+        ),
+        Status::Dropped(site) => ("was freed", site, "value freed here"),
+        Status::MaybeDropped(site) => (
+            "may have been freed",
+            site,
+            "value freed here on some paths",
+        ),
+    };
 
-fn process_inst(state: &mut State, inst: &Inst) -> Result<i32, ExitCode> {
-    match inst {
-        Inst::InstSet(line, inst_set) => {
-            // Allow early exit
-            let _ = process(state, inst_set)?;
+    ctx.report(
+        Diagnostic::error(
+            Code::DanglingBorrow,
+            format!(
+                "use of `{}`, which borrows from `{}`, after `{}` {}",
+                name, loan.owner, loan.owner, what
+            ),
+            span,
+        )
+        .with_label(&loan.span, "borrow taken here")
+        .with_label(site, label),
+    );
+}
+
+/*
+ * The loan a call result holds when the callee returns a borrow of one of its
+ * arguments, as declared with RETURN_BORROW.
+ */
+fn returned_loan(ctx: &Context, state: &State, expr: &ExprDescriptor) -> Option<Loan> {
+    let ExprDescriptor::FunctionCall(span, name, args) = expr else {
+        return None;
+    };
+    let ret_val = ctx.functions.get(name)?.ret_val.as_ref()?;
+    let exclusive = ret_val.borrow == Borrow::Mutable;
+    match args.get(ret_val.derived_from?)? {
+        ExprDescriptor::Place(_, source) => Some(pointer_loan(state, source, exclusive, span)),
+        ExprDescriptor::AddressOf(_, source) => Some(address_loan(state, source, exclusive, span)),
+        _ => None,
+    }
+}
+
+/*
+ * Parts of a place get a binding of their own the first time they are named,
+ * starting out with the value of the whole. Only fields are annotated; what a
+ * pointer points to is not owned through it.
+ */
+fn bind_place(ctx: &Context, state: &mut State, place: &Place) {
+    if state.variables.contains_key(place) {
+        return;
+    }
+    let Some(parent) = place.parent() else {
+        return;
+    };
+    bind_place(ctx, state, &parent);
+
+    let Some(whole) = state.variables.get(&parent) else {
+        return;
+    };
+    let variable = match place.projection.last() {
+        Some(Projection::Field(field)) => {
+            let Some(variable) = whole
+                .variable
+                .ty
+                .record()
+                .and_then(|record| ctx.records.get(record))
+                .and_then(|record| record.fields.iter().find(|(name, _)| name == field))
+                .map(|(_, variable)| variable.clone())
+            else {
+                return;
+            };
+            variable
+        }
+        Some(Projection::Deref | Projection::Index) => {
+            let Some(ty) = whole.variable.ty.pointee() else {
+                return;
+            };
+            Variable {
+                ty: ty.clone(),
+                ownership: false,
+                borrow: Borrow::None,
+                derived_from: None,
+            }
+        }
+        None => return,
+    };
+
+    let binding = Binding::new(variable, &whole.acquired);
+    state.variables.insert(place.clone(), binding);
+}
+
+/* A part can only be reached while the whole is still there */
+fn check_base(ctx: &mut Context, state: &State, place: &Place, span: &Span) -> bool {
+    let Some(parent) = place.parent() else {
+        return true;
+    };
+    if !check_base(ctx, state, &parent, span) {
+        return false;
+    }
+
+    match state.variables.get(&parent) {
+        Some(binding) if !binding.status.is_live() => {
+            check_use(ctx, &parent, &binding.status, span);
+            false
+        }
+        /* Going through a borrow needs what it borrows from */
+        Some(binding) => {
+            if let Some(loan) = &binding.loan {
+                check_source(ctx, state, &parent, loan, span);
+            }
+            true
+        }
+        None => true,
+    }
+}
+
+/* Moving a struct hands over its fields too, so none may be moved out already */
+fn check_partial_move(ctx: &mut Context, state: &State, place: &Place, span: &Span) {
+    let moved = state
+        .variables
+        .iter()
+        .find_map(|(part, binding)| match &binding.status {
+            Status::Moved(site) | Status::MaybeMoved(site) if part.is_within(place) => {
+                Some((part, site))
+            }
+            _ => None,
+        });
+
+    if let Some((part, site)) = moved {
+        ctx.report(
+            Diagnostic::error(
+                Code::UseAfterMove,
+                format!("use of partially moved value `{}`", place),
+                span,
+            )
+            .with_label(site, &format!("field `{}` moved here", part)),
+        );
+    }
+}
+
+/* Owned fields still held when their struct goes away go with it */
+fn release_fields(ctx: &mut Context, state: &State, place: &Place, span: &Span) {
+    let Some(binding) = state.variables.get(place) else {
+        return;
+    };
+    let Some(record) = binding
+        .variable
+        .ty
+        .record()
+        .and_then(|record| ctx.records.get(record))
+    else {
+        return;
+    };
+
+    let value = if binding.variable.ty.pointee().is_some() {
+        place.project(Projection::Deref)
+    } else {
+        place.clone()
+    };
+    let fields: Vec<(Place, Binding)> = record
+        .fields
+        .iter()
+        .map(|(name, variable)| {
+            let path = value.project(Projection::Field(name.clone()));
+            let field = match state.variables.get(&path) {
+                Some(field) => field.clone(),
+                None => Binding::new(variable.clone(), &binding.acquired),
+            };
+            (path, field)
+        })
+        .collect();
+
+    for (path, field) in fields {
+        check_leak(ctx, &path, &field, span);
+    }
+}
+
+/* A value may only be used while its variable still holds it */
+fn check_use(ctx: &mut Context, name: &Place, status: &Status, span: &Span) {
+    let (code, message, site, label) = match status {
+        Status::Live | Status::MaybeOwned => return,
+        Status::Moved(site) => (
+            Code::UseAfterMove,
+            "use of moved value",
+            site,
+            "value moved here",
+        ),
+        Status::MaybeMoved(site) => (
+            Code::UseAfterMove,
+            "use of possibly moved value",
+            site,
+            "value moved here on some paths",
+        ),
+        Status::Dropped(site) => (
+            Code::UseAfterFree,
+            "use of freed value",
+            site,
+            "value freed here",
+        ),
+        Status::MaybeDropped(site) => (
+            Code::UseAfterFree,
+            "use of possibly freed value",
+            site,
+            "value freed here on some paths",
+        ),
+    };
+
+    ctx.report(
+        Diagnostic::error(code, format!("{} `{}`", message, name), span).with_label(site, label),
+    );
+}
+
+/* Passing a variable to an OWNERSHIP_DROP function releases what it owns */
+fn drop_value(ctx: &mut Context, state: &mut State, label: &Place, span: &Span) {
+    bind_place(ctx, state, label);
+    if !check_base(ctx, state, label, span) {
+        return;
+    }
+    if state
+        .variables
+        .get(label)
+        .is_some_and(|binding| binding.status.is_live() && binding.variable.ownership)
+    {
+        release_fields(ctx, state, label, span);
+    }
+
+    let Some(binding) = state.variables.get_mut(label) else {
+        if label.parent().is_none() {
+            ctx.report(Diagnostic::warning(
+                Code::UnknownVariable,
+                format!("use of unknown variable `{}`", label),
+                span,
+            ));
+        }
+        return;
+    };
+
+    match binding.status.clone() {
+        Status::Live | Status::MaybeOwned if binding.variable.ownership => {
+            binding.status = Status::Dropped(span.clone());
+        }
+        Status::Live | Status::MaybeOwned => {
+            ctx.report(Diagnostic::error(
+                Code::FreeNotOwned,
+                format!("cannot free borrowed value `{}`", label),
+                span,
+            ));
+        }
+        Status::Dropped(dropped_at) => {
+            ctx.report(
+                Diagnostic::error(
+                    Code::DoubleFree,
+                    format!("value `{}` is freed twice", label),
+                    span,
+                )
+                .with_label(&dropped_at, "first freed here"),
+            );
+        }
+        Status::MaybeDropped(dropped_at) => {
+            binding.status = Status::Dropped(span.clone());
+            ctx.report(
+                Diagnostic::error(
+                    Code::DoubleFree,
+                    format!("value `{}` may already be freed", label),
+                    span,
+                )
+                .with_label(&dropped_at, "value freed here on some paths"),
+            );
+        }
+        status @ Status::Moved(_) => check_use(ctx, label, &status, span),
+        status @ Status::MaybeMoved(_) => {
+            binding.status = Status::Dropped(span.clone());
+            check_use(ctx, label, &status, span);
+        }
+    }
+}
+
+fn check_argument(
+    ctx: &mut Context,
+    state: &mut State,
+    callee: &str,
+    param_name: &str,
+    param: &Variable,
+    arg: &ExprDescriptor,
+    value: Option<&Variable>,
+) {
+    let param_desc = if param_name.is_empty() {
+        format!("parameter of `{}`", callee)
+    } else {
+        format!("parameter `{}` of `{}`", param_name, callee)
+    };
+
+    if param.ownership {
+        match arg {
+            /* Unknown variables were reported already */
+            ExprDescriptor::Place(span, label) => match value {
+                Some(value) if value.ownership => {
+                    check_partial_move(ctx, state, label, span);
+                    move_out(state, label, span);
+                }
+                Some(_) => {
+                    ctx.report(Diagnostic::error(
+                        Code::BorrowedToMove,
+                        format!(
+                            "cannot move borrowed value `{}` into MOVE {}",
+                            label, param_desc
+                        ),
+                        span,
+                    ));
+                }
+                None => {}
+            },
+            ExprDescriptor::AddressOf(span, label) => {
+                ctx.report(Diagnostic::error(
+                    Code::BorrowedToMove,
+                    format!(
+                        "cannot move borrowed value `&{}` into MOVE {}",
+                        label, param_desc
+                    ),
+                    span,
+                ));
+            }
+            /* Passing no value at all is fine */
+            ExprDescriptor::Literal(_, literal) if is_null_constant(literal) => {}
+            ExprDescriptor::Literal(span, _) | ExprDescriptor::Temporary(span, _) => {
+                ctx.report(Diagnostic::error(
+                    Code::TemporaryNotOwned,
+                    format!(
+                        "MOVE {} requires ownership, but the argument is not an owned value",
+                        param_desc
+                    ),
+                    span,
+                ));
+            }
+            ExprDescriptor::FunctionCall(span, func, _) => {
+                if value.is_some_and(|value| !value.ownership) {
+                    ctx.report(Diagnostic::error(
+                        Code::TemporaryNotOwned,
+                        format!(
+                            "MOVE {} requires ownership, but `{}` does not return an owned value",
+                            param_desc, func
+                        ),
+                        span,
+                    ));
+                }
+            }
+        }
+    }
+
+    /* Only what the pointer points to matters; `int *const` is still writable through */
+    let points_to_const = |ty: &Type| ty.pointee().is_some_and(|pointee| pointee.is_const);
+    let value_is_const = value.is_some_and(|value| points_to_const(&value.ty));
+    if param.ty.pointee().is_some() && !points_to_const(&param.ty) && value_is_const {
+        ctx.report(Diagnostic::error(
+            Code::ConstToMutable,
+            format!("const pointer passed to mutable {}", param_desc),
+            arg.span(),
+        ));
+    }
+}
+
+/* `0`, `0UL`, `0x0`, or a `NULL` that libclang spells out */
+fn is_null_constant(literal: &str) -> bool {
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let digits = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .unwrap_or(digits);
+    matches!(literal, "NULL" | "nullptr") || !digits.is_empty() && digits.chars().all(|c| c == '0')
+}
+
+/* Only owned values may be stored in a MOVE variable or field */
+fn check_owning_assign(
+    ctx: &mut Context,
+    lhs: &Place,
+    rhs: &ExprDescriptor,
+    value: Option<&Variable>,
+) {
+    let owned = value.is_some_and(|value| value.ownership);
+    match rhs {
+        ExprDescriptor::Place(span, label) if value.is_some() && !owned => {
+            ctx.report(Diagnostic::error(
+                Code::BorrowedToMove,
+                format!("cannot move borrowed value `{}` into MOVE `{}`", label, lhs),
+                span,
+            ));
         }
-        Inst::ParamDecl(line, label, variable) => {
-            state.variables.insert(label.to_string(), variable.clone());
+        ExprDescriptor::AddressOf(span, label) => {
+            ctx.report(Diagnostic::error(
+                Code::BorrowedToMove,
+                format!(
+                    "cannot move borrowed value `&{}` into MOVE `{}`",
+                    label, lhs
+                ),
+                span,
+            ));
         }
-        Inst::VarDecl(line, label, variable) => {
-            state.variables.insert(label.to_string(), variable.clone());
+        ExprDescriptor::FunctionCall(span, func, _) if value.is_some() && !owned => {
+            ctx.report(Diagnostic::error(
+                Code::TemporaryNotOwned,
+                format!(
+                    "MOVE `{}` requires ownership, but `{}` does not return an owned value",
+                    lhs, func
+                ),
+                span,
+            ));
         }
-        Inst::Assign(line, lhs, rhs) => {
-            let rv = eval(state, rhs);
+        _ => {}
+    }
+}
+
+/* Ownership of an owned local passes to the callee */
+fn move_out(state: &mut State, label: &Place, span: &Span) {
+    if let Some(binding) = state.variables.get_mut(label) {
+        /* Keep the first move site; later uses were already reported */
+        if binding.variable.ownership && binding.status.is_live() {
+            binding.status = Status::Moved(span.clone());
+        }
+    }
+}
 
-            /* TODO: borrow-checker to verify variables against function parameter list */
+/*
+ * Check a returned value against the return annotation. Returns the variable
+ * whose ownership passes to the caller, if any.
+ */
+fn check_return(ctx: &mut Context, state: &mut State, expr: &ExprDescriptor) -> Option<Place> {
+    let value = eval(ctx, state, expr)?;
+    let function = ctx.current.clone();
+    let owned_return = ctx
+        .functions
+        .get(&function)
+        .and_then(|f| f.ret_val.as_ref())
+        .is_some_and(|ret_val| ret_val.ownership);
 
-            state.variables.insert(lhs.to_string(), rv.unwrap());
+    match expr {
+        /* Every variable here dies with the function, unlike what a pointer points to */
+        ExprDescriptor::AddressOf(span, label) => {
+            let loan = address_loan(state, label, false, span);
+            if loan.address {
+                ctx.report(Diagnostic::error(
+                    Code::DanglingReturn,
+                    format!("cannot return a pointer to local `{}`", loan.owner),
+                    span,
+                ));
+            }
+            None
+        }
+        ExprDescriptor::Place(span, label)
+            if state
+                .variables
+                .get(label)
+                .and_then(|b| b.loan.as_ref())
+                .is_some_and(|loan| loan.address) =>
+        {
+            let loan = state.variables[label].loan.clone().unwrap();
+            ctx.report(
+                Diagnostic::error(
+                    Code::DanglingReturn,
+                    format!(
+                        "cannot return `{}`, which points to local `{}`",
+                        label, loan.owner
+                    ),
+                    span,
+                )
+                .with_label(&loan.span, "borrow taken here"),
+            );
+            None
+        }
+        ExprDescriptor::Place(span, label) if owned_return => {
+            if value.ownership {
+                check_partial_move(ctx, state, label, span);
+                return Some(label.clone());
+            }
+            ctx.report(Diagnostic::error(
+                Code::BorrowedToMove,
+                format!(
+                    "cannot return borrowed value `{}` from MOVE function `{}`",
+                    label, function
+                ),
+                span,
+            ));
+            None
         }
-        Inst::Eval(line, rhs) => {
-            let _ = eval(state, rhs);
+        ExprDescriptor::FunctionCall(span, func, _) if owned_return && !value.ownership => {
+            ctx.report(Diagnostic::error(
+                Code::TemporaryNotOwned,
+                format!(
+                    "MOVE function `{}` must return an owned value, but `{}` does not return one",
+                    function, func
+                ),
+                span,
+            ));
+            None
         }
-        Inst::If(line, inst_list) => match inst_list.len() {
-            2 => {
-                let eval = inst_list.get(0);
-                let path = inst_list.get(1);
-                unsafe {
-                    match fork().unwrap() {
-                        ForkResult::Child => {
-                            /* execute the condition */
-                            state.is_forked = true;
-                            process_inst(state, path.unwrap())?;
+        _ => None,
+    }
+}
+
+/* An owned value still held when its variable goes away is never released */
+fn check_leak(ctx: &mut Context, label: &Place, binding: &Binding, span: &Span) {
+    if !binding.variable.ownership {
+        return;
+    }
+
+    match &binding.status {
+        Status::Live => {
+            ctx.report(
+                Diagnostic::error(
+                    Code::Leak,
+                    format!("owned value `{}` is leaked", label),
+                    span,
+                )
+                .with_label(&binding.acquired, "ownership acquired here"),
+            );
+        }
+        Status::MaybeOwned => {
+            ctx.report(
+                Diagnostic::error(
+                    Code::Leak,
+                    format!("owned value `{}` is leaked on some paths", label),
+                    span,
+                )
+                .with_label(&binding.acquired, "ownership acquired here on some paths"),
+            );
+        }
+        Status::MaybeMoved(site) | Status::MaybeDropped(site) => {
+            let released = match binding.status {
+                Status::MaybeMoved(_) => "moved here on other paths",
+                _ => "freed here on other paths",
+            };
+            ctx.report(
+                Diagnostic::error(
+                    Code::Leak,
+                    format!("owned value `{}` is leaked on some paths", label),
+                    span,
+                )
+                .with_label(&binding.acquired, "ownership acquired here")
+                .with_label(site, released),
+            );
+        }
+        Status::Moved(_) | Status::Dropped(_) => {}
+    }
+}
+
+/*
+ * A pointer into a variable's own storage may not outlive the variable. Loans
+ * on the storage of owners that `dies` still held by places that `outlives`
+ * are reported, once.
+ */
+fn check_outlived(
+    ctx: &mut Context,
+    state: &mut State,
+    span: &Span,
+    dies: impl Fn(&Place) -> bool,
+    outlives: impl Fn(&Place) -> bool,
+) {
+    for (holder, binding) in state.variables.iter_mut() {
+        let dangling = binding
+            .loan
+            .as_ref()
+            .is_some_and(|loan| loan.address && dies(&loan.owner));
+        if !dangling || !outlives(holder) {
+            continue;
+        }
+
+        let loan = binding.loan.take().unwrap();
+        ctx.report(
+            Diagnostic::error(
+                Code::DanglingBorrow,
+                format!(
+                    "`{}` goes away while `{}` still points to it",
+                    loan.owner, holder
+                ),
+                span,
+            )
+            .with_label(&loan.span, "borrow taken here"),
+        );
+    }
+}
+
+/* A struct held by value, whose fields go away with the variable */
+fn is_struct_value(state: &State, label: &Place) -> bool {
+    state.variables.get(label).is_some_and(|binding| {
+        matches!(binding.variable.ty.kind, TypeKind::Record(_))
+            && !binding.variable.ownership
+            && binding.status == Status::Live
+    })
+}
+
+/* What an operand reads or assigns; it declares nothing and does not branch */
+fn process_operands(ctx: &mut Context, state: &mut State, insts: &[Inst]) {
+    for inst in insts {
+        match inst {
+            Inst::InstSet(_, inst_set) => process_operands(ctx, state, inst_set),
+            Inst::Eval(_, _) | Inst::Assign(_, _, _) => process_inst(ctx, state, inst),
+            /* Statement expressions are not followed */
+            _ => {}
+        }
+    }
+}
+
+/* Transfer function of a single straight-line instruction */
+fn process_inst(ctx: &mut Context, state: &mut State, inst: &Inst) {
+    match inst {
+        Inst::ParamDecl(span, label, variable) | Inst::VarDecl(span, label, variable) => {
+            let place = Place::variable(label);
+            let mut binding = Binding::new(variable.clone(), span);
+            /* A local holds nothing until its initializer or first assignment */
+            if let Inst::VarDecl(..) = inst {
+                binding.variable.ownership = false;
+            }
+            forget_parts(state, &place);
+            state.variables.insert(place, binding);
+        }
+        Inst::Assign(span, lhs, rhs) => {
+            bind_place(ctx, state, lhs);
+            check_base(ctx, state, lhs, span);
+            let declared = state.variables.get(lhs).map(|b| b.variable.clone());
+            let must_own = state.variables.get(lhs).is_some_and(|b| b.must_own);
+            let rv = eval(ctx, state, rhs);
+            if must_own {
+                check_owning_assign(ctx, lhs, rhs, rv.as_ref());
+            }
+            /* What `lhs` still owns is lost once it is overwritten */
+            if let Some(binding) = state.variables.get(lhs).cloned() {
+                check_leak(ctx, lhs, &binding, span);
+            }
+
+            match (declared, rhs) {
+                /* An annotated borrow keeps its annotation and holds a loan on the owner */
+                (Some(variable), ExprDescriptor::Place(span, label))
+                    if variable.borrow != Borrow::None =>
+                {
+                    let exclusive = variable.borrow == Borrow::Mutable;
+                    let loan = pointer_loan(state, label, exclusive, span);
+                    check_loan(ctx, state, lhs, &loan, &[]);
+
+                    let mut binding = Binding::new(variable, span);
+                    binding.loan = Some(loan);
+                    forget_parts(state, lhs);
+                    state.variables.insert(lhs.clone(), binding);
+                }
+                /* `q = &x` borrows `x` for as long as `q` holds the address */
+                (declared, ExprDescriptor::AddressOf(span, label)) => {
+                    let Some(rv) = rv else {
+                        return;
+                    };
+                    let variable = match declared {
+                        Some(variable) if variable.borrow != Borrow::None => variable,
+                        _ => rv,
+                    };
+                    let exclusive = variable.borrow == Borrow::Mutable;
+                    let loan = address_loan(state, label, exclusive, span);
+                    check_loan(ctx, state, lhs, &loan, &[]);
+
+                    let mut binding = Binding::new(variable, span);
+                    binding.loan = Some(loan);
+                    forget_parts(state, lhs);
+                    state.variables.insert(lhs.clone(), binding);
+                }
+                /* A constant such as NULL, or a value computed from others, leaves nothing to release */
+                (
+                    Some(mut variable),
+                    ExprDescriptor::Literal(span, _) | ExprDescriptor::Temporary(span, _),
+                ) => {
+                    variable.ownership = false;
+                    forget_parts(state, lhs);
+                    state
+                        .variables
+                        .insert(lhs.clone(), Binding::new(variable, span));
+                }
+                /*
+                 * Assigning a fresh value makes a moved-from variable usable
+                 * again. It keeps its declared type and annotations, and owns
+                 * the value if the value was owned, taking it from the source.
+                 */
+                (declared, _) => {
+                    if let Some(rv) = rv {
+                        let mut variable = declared.unwrap_or_else(|| rv.clone());
+                        variable.ownership = must_own || rv.ownership;
+                        if let ExprDescriptor::Place(span, label) = rhs {
+                            if rv.ownership && label != lhs {
+                                check_partial_move(ctx, state, label, span);
+                                move_out(state, label, span);
+                            }
                         }
-                        ForkResult::Parent { child } => {
-                            /* wait for pid */
-                            waitpid(Some(child), None).unwrap();
+
+                        let mut binding = Binding::new(variable, rhs.span());
+                        binding.loan = returned_loan(ctx, state, rhs);
+                        if let Some(loan) = &binding.loan {
+                            check_loan(ctx, state, lhs, loan, &[]);
+                        }
+                        /* A copy of a pointer into a local dangles with it */
+                        if let ExprDescriptor::Place(_, label) = rhs {
+                            let copied = state.variables.get(label).and_then(|b| b.loan.as_ref());
+                            if let Some(loan) = copied.filter(|loan| loan.address) {
+                                binding.loan = Some(loan.clone());
+                            }
                         }
+                        forget_parts(state, lhs);
+                        state.variables.insert(lhs.clone(), binding);
                     }
                 }
             }
-            3 => {
-                let eval = inst_list.get(0);
-                let p1 = inst_list.get(1);
-                let p2 = inst_list.get(2);
-                /* TODO: enable both path */
-                unsafe {
-                    // match fork().unwrap() {
-                    //     ForkResult::Child => {
-                    //         /* execute the condition */
-                    //         state.is_forked = true;
-                    //         process_inst(state, p1.unwrap())?;
-                    //     }
-                    //     ForkResult::Parent { child } => {
-                    //         /* wait for pid */
-                    //         waitpid(Some(child), None).unwrap();
-                    process_inst(state, p1.unwrap())?;
-                    // }
-                    // }
-                }
-            }
-            _ => {
-                unreachable!();
+            if let Some(binding) = state.variables.get_mut(lhs) {
+                binding.must_own = must_own;
             }
-        },
-        Inst::ReturnStmt(line, _) => {
-            return Err(ExitCode::EarlyExit);
         }
-        Inst::FieldDecl(line, _, _) => todo!(),
+        Inst::EndScope(span, labels) => {
+            for label in labels {
+                let place = Place::variable(label);
+                if is_struct_value(state, &place) {
+                    release_fields(ctx, state, &place, span);
+                }
+                if let Some(binding) = state.variables.remove(&place) {
+                    check_leak(ctx, &place, &binding, span);
+                }
+                forget_parts(state, &place);
+            }
+            check_outlived(
+                ctx,
+                state,
+                span,
+                |owner| labels.contains(&owner.base),
+                |_| true,
+            );
+        }
+        Inst::Eval(_, rhs) => {
+            let _ = eval(ctx, state, rhs);
+        }
+        /* Everything but the returned variable goes away; the edge to the exit is in the CFG */
+        Inst::ReturnStmt(span, value) => {
+            let returned = value
+                .as_ref()
+                .and_then(|expr| check_return(ctx, state, expr));
+            let indirect: Vec<Place> = state
+                .variables
+                .keys()
+                .filter(|holder| !is_direct(state, holder))
+                .cloned()
+                .collect();
+            check_outlived(
+                ctx,
+                state,
+                span,
+                |_| true,
+                |holder| indirect.contains(holder),
+            );
+            for (label, binding) in state.variables.iter() {
+                /* Parts go with the whole */
+                if Some(label) == returned.as_ref() || label.parent().is_some() {
+                    continue;
+                }
+                if is_struct_value(state, label) {
+                    release_fields(ctx, state, label, span);
+                }
+                check_leak(ctx, label, binding, span);
+            }
+        }
+        Inst::InstSet(_, _)
+        | Inst::If(_, _)
+        | Inst::While(_, _)
+        | Inst::DoWhile(_, _)
+        | Inst::For(_, _)
+        | Inst::Switch(_, _)
+        | Inst::Case(_, _)
+        | Inst::Default(_, _)
+        | Inst::Break(_)
+        | Inst::Continue(_)
+        | Inst::Label(_, _, _)
+        | Inst::Goto(_, _) => unreachable!("control flow is lowered by the CFG"),
+        Inst::FieldDecl(_, _, _) => unreachable!("fields are collected into their record"),
     }
+}
 
-    Ok(0)
+fn process_block(ctx: &mut Context, state: &mut State, block: &Block) {
+    for inst in block.inst.iter() {
+        process_inst(ctx, state, inst);
+    }
 }
 
-fn process(state: &mut State, inst_list: &Vec<Inst>) -> Result<i32, ExitCode> {
-    for inst in inst_list {
-        process_inst(state, inst)?;
+/*
+ * Forward dataflow over the CFG. States are propagated along the edges until
+ * nothing changes, then every reachable block is visited once more with its
+ * final entry state to report diagnostics exactly once. Returns the state on
+ * leaving the function, if it can be left at all.
+ */
+fn analyse(ctx: &mut Context, cfg: &Cfg, entry: State) -> Option<State> {
+    let mut states: Vec<Option<State>> = vec![None; cfg.blocks.len()];
+    states[cfg.entry] = Some(entry);
+
+    let mut worklist = VecDeque::from([cfg.entry]);
+    ctx.report = false;
+    while let Some(id) = worklist.pop_front() {
+        let mut state = match &states[id] {
+            Some(state) => state.clone(),
+            None => continue,
+        };
+        process_block(ctx, &mut state, &cfg.blocks[id]);
+
+        for &succ in cfg.blocks[id].succ.iter() {
+            let changed = match &mut states[succ] {
+                Some(current) => {
+                    let before = current.clone();
+                    current.join(&state);
+                    *current != before
+                }
+                None => {
+                    states[succ] = Some(state.clone());
+                    true
+                }
+            };
+            if changed && !worklist.contains(&succ) {
+                worklist.push_back(succ);
+            }
+        }
     }
 
-    Ok(0)
+    ctx.report = true;
+    let first = ctx.diagnostics.len();
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        if let Some(state) = &states[id] {
+            process_block(ctx, &mut state.clone(), block);
+        }
+    }
+
+    /* Blocks are not numbered in source order */
+    ctx.diagnostics[first..].sort_by_key(|d| (d.span.begin.line, d.span.begin.col));
+
+    states[cfg.exit].take()
 }
 
 // fn count_if_inst(inst: &Inst) -> u32 {
@@ -144,32 +1259,379 @@ fn process(state: &mut State, inst_list: &Vec<Inst>) -> Result<i32, ExitCode> {
 //     ifs
 // }
 
-pub fn verify(tl: &TranslationUnit) {
-    // let variables = HashMap::new();
-    let mut state = State {
-        variables: HashMap::new(),
+pub fn verify(tl: &TranslationUnit) -> Vec<Diagnostic> {
+    let mut ctx = Context {
         functions: HashMap::new(),
-        is_forked: false,
+        records: HashMap::new(),
+        current: String::new(),
+        diagnostics: Vec::new(),
+        report: true,
     };
     for unit in tl.sub_unit.iter() {
         match unit {
             TranslationUnitSet::Function(function) => {
-                // let ifs = count_if(&function.inst);
-                let rv = process(&mut state, &function.inst);
-                match rv {
-                    Ok(_) => {
-                        /* Insert into function map for later lookup */
-                        state
-                            .functions
-                            .insert(function.name.clone(), function.clone());
-                    }
-                    Err(code) => match code {
-                        ExitCode::EarlyExit => {
-                            exit(0);
-                        }
-                    },
+                /* Insert into function map for later lookup, recursion included */
+                let function = match ctx.functions.get(&function.name) {
+                    Some(earlier) => merge_declaration(earlier, function),
+                    None => function.as_ref().clone(),
+                };
+                ctx.functions
+                    .insert(function.name.clone(), function.clone());
+
+                /* Prototypes have nothing to check */
+                if function.body_span.is_none() {
+                    continue;
                 }
+
+                ctx.current = function.name.clone();
+                let cfg = Cfg::build(&function);
+                let _ = analyse(&mut ctx, &cfg, State::default());
+            }
+            TranslationUnitSet::Record(record) => {
+                ctx.records.insert(record.name.clone(), record.clone());
             }
         }
     }
-}
\ No newline at end of file
+
+    ctx.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Each statement on its own line, so a diagnostic is known by code and line */
+    fn at(line: u64) -> Span {
+        let location = Location {
+            file: "t.c".to_string(),
+            line,
+            col: 1,
+        };
+        Span {
+            begin: location.clone(),
+            end: location,
+        }
+    }
+
+    const END: u64 = 99;
+
+    fn int_ptr() -> Type {
+        Type::new(TypeKind::Pointer(Box::new(Type::new(TypeKind::Scalar(
+            "int".to_string(),
+        )))))
+    }
+
+    fn plain(ty: Type) -> Variable {
+        Variable {
+            ty,
+            ownership: false,
+            borrow: Borrow::None,
+            derived_from: None,
+        }
+    }
+
+    fn owned(ty: Type) -> Variable {
+        Variable {
+            ownership: true,
+            ..plain(ty)
+        }
+    }
+
+    fn borrowed(ty: Type, borrow: Borrow) -> Variable {
+        Variable {
+            borrow,
+            ..plain(ty)
+        }
+    }
+
+    fn place(name: &str) -> Place {
+        Place::variable(name)
+    }
+
+    fn read(line: u64, place: Place) -> ExprDescriptor {
+        ExprDescriptor::Place(at(line), place)
+    }
+
+    fn call(line: u64, callee: &str, args: Vec<ExprDescriptor>) -> ExprDescriptor {
+        ExprDescriptor::FunctionCall(at(line), callee.to_string(), args)
+    }
+
+    /* A call whose result is thrown away */
+    fn stmt(line: u64, callee: &str, args: Vec<Place>) -> Inst {
+        let args = args.into_iter().map(|arg| read(line, arg)).collect();
+        Inst::Eval(at(line), call(line, callee, args))
+    }
+
+    /* Lowered like a declaration statement: the variable, then its initial value */
+    fn declare(line: u64, name: &str, variable: Variable, init: ExprDescriptor) -> Vec<Inst> {
+        vec![
+            Inst::VarDecl(at(line), name.to_string(), variable),
+            Inst::Assign(at(line), place(name), init),
+        ]
+    }
+
+    fn function(name: &str, params: Vec<(&str, Variable)>, body: Vec<Inst>) -> TranslationUnitSet {
+        let param: Vec<(String, Variable)> = params
+            .into_iter()
+            .map(|(name, variable)| (name.to_string(), variable))
+            .collect();
+        let mut inst: Vec<Inst> = param
+            .iter()
+            .map(|(name, variable)| Inst::ParamDecl(at(0), name.clone(), variable.clone()))
+            .collect();
+        inst.extend(body);
+        TranslationUnitSet::Function(Box::new(Function {
+            name: name.to_string(),
+            span: at(0),
+            param,
+            ret_val: None,
+            drop: false,
+            inst,
+            body_span: Some(at(END)),
+        }))
+    }
+
+    fn prototype(
+        name: &str,
+        params: Vec<Variable>,
+        ret_val: Option<Variable>,
+    ) -> TranslationUnitSet {
+        TranslationUnitSet::Function(Box::new(Function {
+            name: name.to_string(),
+            span: at(0),
+            param: params
+                .into_iter()
+                .map(|variable| (String::new(), variable))
+                .collect(),
+            ret_val,
+            drop: false,
+            inst: Vec::new(),
+            body_span: None,
+        }))
+    }
+
+    /* consume takes ownership, release frees, alloc hands out, peek only looks */
+    fn library() -> Vec<TranslationUnitSet> {
+        let release = match prototype("release", vec![plain(int_ptr())], None) {
+            TranslationUnitSet::Function(mut function) => {
+                function.drop = true;
+                TranslationUnitSet::Function(function)
+            }
+            record => record,
+        };
+        vec![
+            prototype("consume", vec![owned(int_ptr())], None),
+            release,
+            prototype("alloc", Vec::new(), Some(owned(int_ptr()))),
+            prototype("peek", vec![plain(int_ptr())], None),
+        ]
+    }
+
+    fn codes(units: Vec<TranslationUnitSet>) -> Vec<(&'static str, u64)> {
+        let mut tl = TranslationUnit {
+            sub_unit: library(),
+        };
+        tl.sub_unit.extend(units);
+        verify(&tl)
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.span.begin.line))
+            .collect()
+    }
+
+    fn cond() -> Inst {
+        Inst::Eval(at(0), read(0, place("c")))
+    }
+
+    #[test]
+    fn use_after_move() {
+        let body = vec![
+            stmt(1, "consume", vec![place("p")]),
+            stmt(2, "peek", vec![place("p")]),
+        ];
+        let f = function("f", vec![("p", owned(int_ptr()))], body);
+        assert_eq!(codes(vec![f]), vec![("E0002", 2)]);
+    }
+
+    #[test]
+    fn maybe_moved_after_if() {
+        let body = vec![
+            Inst::If(
+                at(1),
+                vec![
+                    cond(),
+                    stmt(2, "consume", vec![place("p")]),
+                    Inst::InstSet(at(3), Vec::new()),
+                ],
+            ),
+            stmt(4, "peek", vec![place("p")]),
+        ];
+        let params = vec![
+            ("p", owned(int_ptr())),
+            ("c", plain(Type::new(TypeKind::Void))),
+        ];
+        assert_eq!(
+            codes(vec![function("f", params, body)]),
+            vec![("E0002", 4), ("E0006", END)]
+        );
+    }
+
+    /* The second time round, the first iteration has moved it */
+    #[test]
+    fn loop_back_edge() {
+        let body = vec![Inst::While(
+            at(1),
+            vec![cond(), stmt(2, "consume", vec![place("p")])],
+        )];
+        let params = vec![
+            ("p", owned(int_ptr())),
+            ("c", plain(Type::new(TypeKind::Void))),
+        ];
+        assert_eq!(
+            codes(vec![function("f", params, body)]),
+            vec![("E0002", 2), ("E0006", END)]
+        );
+    }
+
+    /* Each failure jumps to the label that frees what was acquired so far */
+    fn ladder(second_failure: &str) -> TranslationUnitSet {
+        let fail = |line: u64, label: &str| {
+            Inst::If(
+                at(line),
+                vec![cond(), Inst::Goto(at(line), label.to_string())],
+            )
+        };
+        let body = [
+            declare(1, "a", owned(int_ptr()), call(1, "alloc", Vec::new())),
+            vec![fail(2, "fail")],
+            declare(3, "b", owned(int_ptr()), call(3, "alloc", Vec::new())),
+            vec![
+                fail(4, second_failure),
+                stmt(5, "release", vec![place("b")]),
+                stmt(6, "release", vec![place("a")]),
+                Inst::ReturnStmt(at(7), None),
+                Inst::Label(
+                    at(8),
+                    "fail_b".to_string(),
+                    vec![stmt(8, "release", vec![place("b")])],
+                ),
+                Inst::Label(
+                    at(9),
+                    "fail".to_string(),
+                    vec![stmt(9, "release", vec![place("a")])],
+                ),
+            ],
+        ]
+        .concat();
+        function("f", vec![("c", plain(Type::new(TypeKind::Void)))], body)
+    }
+
+    #[test]
+    fn goto_ladder() {
+        assert_eq!(codes(vec![ladder("fail_b")]), vec![]);
+        assert_eq!(codes(vec![ladder("fail")]), vec![("E0006", END)]);
+    }
+
+    #[test]
+    fn leak() {
+        let body = declare(1, "p", owned(int_ptr()), call(1, "alloc", Vec::new()));
+        assert_eq!(
+            codes(vec![function("f", Vec::new(), body)]),
+            vec![("E0006", END)]
+        );
+    }
+
+    #[test]
+    fn double_free() {
+        let body = vec![
+            stmt(1, "release", vec![place("p")]),
+            stmt(2, "release", vec![place("p")]),
+        ];
+        let f = function("f", vec![("p", owned(int_ptr()))], body);
+        assert_eq!(codes(vec![f]), vec![("E0008", 2)]);
+    }
+
+    /* A BORROW_MUT is exclusive while it is held */
+    #[test]
+    fn borrow_conflict() {
+        let body = [
+            declare(
+                1,
+                "q",
+                borrowed(int_ptr(), Borrow::Mutable),
+                read(1, place("p")),
+            ),
+            declare(
+                2,
+                "r",
+                borrowed(int_ptr(), Borrow::Shared),
+                read(2, place("p")),
+            ),
+            vec![
+                stmt(3, "peek", vec![place("q")]),
+                stmt(4, "release", vec![place("p")]),
+            ],
+        ]
+        .concat();
+        let f = function("f", vec![("p", owned(int_ptr()))], body);
+        assert_eq!(codes(vec![f]), vec![("E0010", 2)]);
+    }
+
+    /* What `first` returns points into its argument, so it dies with it */
+    #[test]
+    fn return_borrow() {
+        let ret_val = Variable {
+            derived_from: Some(0),
+            ..plain(int_ptr())
+        };
+        let first = prototype("first", vec![plain(int_ptr())], Some(ret_val));
+        let body = [
+            declare(
+                1,
+                "q",
+                plain(int_ptr()),
+                call(1, "first", vec![read(1, place("p"))]),
+            ),
+            vec![
+                stmt(2, "release", vec![place("p")]),
+                stmt(3, "peek", vec![place("q")]),
+            ],
+        ]
+        .concat();
+        let f = function("f", vec![("p", owned(int_ptr()))], body);
+        assert_eq!(codes(vec![first, f]), vec![("E0011", 3)]);
+    }
+
+    /* A field moved out can not be moved again, nor the struct as a whole */
+    #[test]
+    fn field_moves() {
+        let pair = TranslationUnitSet::Record(Record {
+            name: "pair".to_string(),
+            fields: vec![
+                ("a".to_string(), owned(int_ptr())),
+                ("b".to_string(), owned(int_ptr())),
+            ],
+        });
+        let pair_ty = Type::new(TypeKind::Record("pair".to_string()));
+        let consume_pair = prototype("consume_pair", vec![owned(pair_ty.clone())], None);
+        let field = |name: &str| place("s").project(Projection::Field(name.to_string()));
+        let body = vec![
+            stmt(1, "consume", vec![field("a")]),
+            stmt(2, "consume", vec![field("a")]),
+            stmt(3, "consume", vec![field("b")]),
+            stmt(4, "consume_pair", vec![place("s")]),
+        ];
+        let f = function("f", vec![("s", owned(pair_ty))], body);
+        assert_eq!(
+            codes(vec![pair, consume_pair, f]),
+            vec![("E0002", 2), ("E0002", 4)]
+        );
+    }
+
+    /* The definition leaves the parameter plain; the prototype said MOVE */
+    #[test]
+    fn annotation_from_prototype() {
+        let proto = prototype("keep", vec![owned(int_ptr())], None);
+        let definition = function("keep", vec![("p", plain(int_ptr()))], Vec::new());
+        assert_eq!(codes(vec![proto, definition]), vec![("E0006", END)]);
+    }
+}
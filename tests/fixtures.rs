@@ -0,0 +1,255 @@
+use std::process::Command;
+
+/* Runs the checker on a fixture; whether it passed, and what it reported */
+fn check(fixture: &str) -> (bool, String) {
+    let (success, stdout, stderr) = run(fixture);
+    assert!(stdout.contains(" 0 failed"), "{}{}", stdout, stderr);
+    (success, stderr)
+}
+
+fn run(fixture: &str) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_c_borrow_checker"))
+        .arg(format!("tests/fixtures/{}", fixture))
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/*
+ * The dump names the file as clang was given it, relative to where it ran;
+ * libclang names it as the checker was given it
+ */
+fn relative(stderr: &str) -> String {
+    stderr.replace("tests/fixtures/", "")
+}
+
+/* A state machine over an enum; only the fallthrough frees twice */
+#[test]
+fn switch_over_enum() {
+    let (success, stderr) = check("enum_switch.json");
+    assert!(!success, "{}", stderr);
+    assert_eq!(
+        stderr
+            .lines()
+            .filter(|line| line.contains(": error["))
+            .count(),
+        1,
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("enum_switch.c:17:13: error[E0008]"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("warning["), "{}", stderr);
+}
+
+/*
+ * Array sizes, `sizeof`, function pointer parameters and attributes other than
+ * annotations come out of libclang differently than out of the JSON dump; both
+ * must lower to the same IR
+ */
+#[test]
+fn frontends_agree() {
+    let (success, stderr) = check("frontends.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("frontends.c:14:8: error[E0007]"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("frontends.c:20:1: error[E0006]"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("warning["), "{}", stderr);
+
+    let (_, stdout, from_source) = run("frontends.c");
+    if !stdout.contains(" 0 failed") {
+        eprintln!("libclang is not usable here, skipping: {}", from_source);
+        return;
+    }
+    assert_eq!(relative(&from_source), stderr);
+}
+
+/* The dump leaves out a line it has just given, and macros have two locations */
+#[test]
+fn elided_and_macro_locations() {
+    let (success, stderr) = check("locations.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("locations.c:7:68: error[E0006]: owned value `p` is leaked"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 1, "{}", stderr);
+}
+
+/*
+ * The dump has no annotation text; it is read back from the source, wherever
+ * the attribute was spelled
+ */
+#[test]
+fn annotation_spellings() {
+    let (success, stderr) = check("annotations.json");
+    assert!(!success, "{}", stderr);
+    for leak in [
+        "annotations.c:8:43: error[E0006]: owned value `p` is leaked", /* header */
+        "annotations.c:10:35: error[E0006]: owned value `p` is leaked", /* macro */
+        "annotations.c:12:39: error[E0006]: owned value `p` is leaked", /* [[clang::annotate]] */
+        "annotations.c:14:42: error[E0006]: owned value `a` is leaked", /* two on one line */
+        "annotations.c:14:42: error[E0006]: owned value `b` is leaked",
+    ] {
+        assert!(stderr.contains(leak), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error[").count(), 5, "{}", stderr);
+
+    /* The text is behind a macro, so there is no string to read */
+    assert!(
+        stderr.contains("annotations.c:16:32: warning[W0004]"),
+        "{}",
+        stderr
+    );
+}
+
+/* Only an owned value or no value at all may be passed to a MOVE parameter */
+#[test]
+fn move_parameter_arguments() {
+    let (success, stderr) = check("move_arguments.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("move_arguments.c:10:11: error[E0004]"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("move_arguments.c:11:18: error[E0004]"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 2, "{}", stderr);
+}
+
+/* Attributes and doc comments are neither function bodies nor unsupported code */
+#[test]
+fn attributes_and_comments() {
+    let (success, stderr) = check("attributes.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("attributes.c:12:26: error[E0007]"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("attributes.c:13:11: error[E0008]"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 2, "{}", stderr);
+    assert!(!stderr.contains("warning["), "{}", stderr);
+}
+
+/* A MOVE variable declared without an initializer owns nothing yet */
+#[test]
+fn uninitialized_move_variables() {
+    let (success, stderr) = check("declarations.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("declarations.c:23:1: error[E0006]: owned value `p` is leaked"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 1, "{}", stderr);
+}
+
+/* Ownership taken on one path only, or dropped by overwriting the owner */
+#[test]
+fn owned_on_some_paths() {
+    let (success, stderr) = check("owned_paths.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr
+            .contains("owned_paths.c:10:1: error[E0006]: owned value `p` is leaked on some paths"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("owned_paths.c:21:3: error[E0006]: owned value `p` is leaked"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 2, "{}", stderr);
+}
+
+/* RETURN_BORROW on an unnamed prototype parameter ties the result to that parameter */
+#[test]
+fn return_borrow_by_position() {
+    let (success, stderr) = check("return_borrow.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains(
+            "return_borrow.c:11:3: error[E0011]: use of `q`, which borrows from `b`, after `b` was freed"
+        ),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 1, "{}", stderr);
+}
+
+/* Array indices are read, and an assignment of a computed value replaces the old one */
+#[test]
+fn operands_of_places_and_assignments() {
+    let (success, stderr) = check("operands.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("operands.c:8:14: error[E0007]: use of freed value `p`"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("operands.c:20:7: error[E0007]: use of freed value `p`"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 2, "{}", stderr);
+}
+
+/* An array held in a local is part of the frame, whether or not it decays first */
+#[test]
+fn returned_local_arrays() {
+    let (success, stderr) = check("local_arrays.json");
+    assert!(!success, "{}", stderr);
+    for error in [
+        "local_arrays.c:9:10: error[E0009]: cannot return a pointer to local `buf[_]`",
+        "local_arrays.c:14:10: error[E0009]: cannot return a pointer to local `buf[_]`",
+        "local_arrays.c:20:10: error[E0009]: cannot return `p`, which points to local `buf[_]`",
+        "local_arrays.c:25:10: error[E0009]: cannot return a pointer to local `m.text[_]`",
+    ] {
+        assert!(stderr.contains(error), "{}", stderr);
+    }
+    assert_eq!(stderr.matches("error[").count(), 4, "{}", stderr);
+}
+
+/* A typedef carries its MOVE annotation, also through another typedef */
+#[test]
+fn typedef_annotation_inheritance() {
+    let (success, stderr) = check("typedefs.json");
+    assert!(!success, "{}", stderr);
+    assert!(
+        stderr.contains("typedefs.c:12:1: error[E0006]: owned value `b` is leaked"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("typedefs.c:17:11: error[E0008]: value `p` is freed twice"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("error[").count(), 2, "{}", stderr);
+}
@@ -0,0 +1,16 @@
+#include "annotations.h"
+
+#define TAKE(type, name) MOVE type *name
+#define ANNOTATION "MOVE"
+
+[[clang::annotate("MOVE")]] int *make_c23(void);
+
+void from_header(void) { int *p = make(); }
+
+void through_macro(TAKE(int, p)) {}
+
+void c23(void) { int *p = make_c23(); }
+
+void one_line(MOVE int *a, MOVE int *b) {}
+
+void unreadable(__attribute__((annotate(ANNOTATION))) int *p) {}
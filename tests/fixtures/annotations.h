@@ -0,0 +1,5 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+MOVE int *make(void);
+OWNERSHIP_DROP void release(void *p);
@@ -0,0 +1,14 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+/** Takes `p` over; it must not be null. */
+void consume(MOVE int *p) __attribute__((nonnull));
+OWNERSHIP_DROP void release(void *p) __attribute__((nonnull, nothrow));
+int log_line(const char *format, ...) __attribute__((format(printf, 1, 2)));
+
+/// Frees `p`, then frees it again.
+__attribute__((noinline)) void twice(MOVE int *p) {
+  release(p);
+  log_line("%p", (void *)p);
+  release(p);
+}
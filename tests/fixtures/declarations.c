@@ -0,0 +1,23 @@
+#define MOVE __attribute__((annotate("MOVE")))
+
+MOVE int *alloc(void);
+void consume(MOVE int *p);
+
+void early_return(int n) {
+  MOVE int *p;
+  if (n)
+    return;
+  p = alloc();
+  consume(p);
+}
+
+void never_assigned(void) {
+  MOVE int *p;
+}
+
+void assigned_then_leaked(int n) {
+  MOVE int *p;
+  if (n)
+    return;
+  p = alloc();
+}
@@ -0,0 +1,23 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+OWNERSHIP_DROP void release(void *p);
+
+enum state { STATE_A, STATE_B, STATE_DONE };
+
+void step(enum state s, MOVE int *buf) {
+  switch (s) {
+  case STATE_A:
+    release(buf);
+    break;
+  case STATE_B:
+    release(buf);
+    /* falls through */
+  case STATE_DONE:
+    release(buf);
+    break;
+  default:
+    release(buf);
+    break;
+  }
+}
@@ -0,0 +1,20 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+OWNERSHIP_DROP void release(void *p) __attribute__((nonnull));
+MOVE void *acquire(unsigned long size) __attribute__((warn_unused_result));
+int sum(const int values[4]) __attribute__((pure));
+void each(int *items, int count, void (*visit)(int *item, int index));
+
+void visit_all(MOVE int *items, int count, void (*visit)(int *item, int index)) {
+  int scratch[sizeof(int) * 2] = {0};
+  each(items, count, visit);
+  release(items);
+  scratch[0] = sum(scratch);
+  each(items, count, visit);
+}
+
+void leak(void) {
+  MOVE int *buf = acquire(sizeof(int) * 4);
+  buf[0] = sum(buf);
+}
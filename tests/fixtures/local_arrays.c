@@ -0,0 +1,37 @@
+#define MOVE __attribute__((annotate("MOVE")))
+
+struct message {
+  char text[16];
+};
+
+char *decayed(void) {
+  char buf[16];
+  return buf;
+}
+
+char *first_element(void) {
+  char buf[16];
+  return &buf[0];
+}
+
+char *through_copy(void) {
+  char buf[16];
+  char *p = buf;
+  return p;
+}
+
+char *field_array(void) {
+  struct message m;
+  return m.text;
+}
+
+char *behind_pointer(char *out, struct message *m) {
+  if (out[0])
+    return &out[1];
+  return m->text;
+}
+
+int element_value(void) {
+  int values[4] = {1, 2, 3, 4};
+  return values[2];
+}
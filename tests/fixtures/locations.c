@@ -0,0 +1,8 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+#define DEFINE_RELEASE(name) void name(MOVE int *p) { release(p); }
+
+OWNERSHIP_DROP void release(void *p);
+
+void first(MOVE int *p) { release(p); } void second(MOVE int *p) { }
+DEFINE_RELEASE(third)
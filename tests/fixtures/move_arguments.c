@@ -0,0 +1,13 @@
+#define NULL ((void *)0)
+
+#define MOVE __attribute__((annotate("MOVE")))
+
+void consume(MOVE int *p);
+
+void arguments(MOVE int *p, int *q) {
+  consume(NULL);
+  consume(0);
+  consume(q + 1);
+  consume((int *)42);
+  consume(p);
+}
@@ -0,0 +1,21 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+OWNERSHIP_DROP void release(void *p);
+
+int index_after_release(MOVE int *p, int *arr) {
+  release(p);
+  return arr[*p];
+}
+
+int *reassigned(int *r) {
+  int local = 0;
+  int *q = &local;
+  q = r + 1;
+  return q;
+}
+
+void store_after_release(MOVE int *p, int *arr) {
+  release(p);
+  arr[*p] = 0;
+}
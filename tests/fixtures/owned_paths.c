@@ -0,0 +1,30 @@
+#define MOVE __attribute__((annotate("MOVE")))
+
+MOVE int *alloc(void);
+void consume(MOVE int *p);
+
+void assigned_on_one_path(int n) {
+  MOVE int *p = 0;
+  if (n)
+    p = alloc();
+}
+
+void consumed_after_join(int n) {
+  MOVE int *p = 0;
+  if (n)
+    p = alloc();
+  consume(p);
+}
+
+void overwritten(void) {
+  MOVE int *p = alloc();
+  p = alloc();
+  consume(p);
+}
+
+void overwritten_after_move(void) {
+  MOVE int *p = alloc();
+  consume(p);
+  p = alloc();
+  consume(p);
+}
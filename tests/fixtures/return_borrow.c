@@ -0,0 +1,13 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+#define RETURN_BORROW __attribute__((annotate("RETURN_BORROW")))
+
+OWNERSHIP_DROP void release(void *p);
+int *second(int *, RETURN_BORROW int *);
+
+void borrow_second(MOVE int *a, MOVE int *b) {
+  int *q = second(a, b);
+  release(b);
+  *q = 1;
+  release(a);
+}
@@ -0,0 +1,23 @@
+#define MOVE __attribute__((annotate("MOVE")))
+#define OWNERSHIP_DROP __attribute__((annotate("OWNERSHIP_DROP")))
+
+typedef MOVE int *owned_ptr;
+typedef owned_ptr buffer;
+
+owned_ptr alloc(void);
+void release(buffer b) OWNERSHIP_DROP;
+
+void leaks(void) {
+  buffer b = alloc();
+}
+
+void uses_after_release(void) {
+  owned_ptr p = alloc();
+  release(p);
+  release(p);
+}
+
+void releases(void) {
+  buffer b = alloc();
+  release(b);
+}